@@ -0,0 +1,423 @@
+use crate::config::{CollisionPolicy, ErrorPolicy};
+use crate::error::SuperPointError;
+use crate::preprocessing;
+use log::{error, info, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// File name of the manifest written into the output directory of a batch run.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// One image of a batch run and where its annotated output goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchItem {
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub keypoints: usize,
+    /// Index of the worker (device) that processed the image
+    pub worker: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailedEntry {
+    pub input: PathBuf,
+    pub error: String,
+}
+
+/// What a batch run got through, in input order. Images never started because the run was
+/// interrupted or aborted appear in neither list.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub completed: Vec<ManifestEntry>,
+    pub failed: Vec<FailedEntry>,
+    /// Set when a stop request left some images unprocessed
+    pub interrupted: bool,
+    /// Set when a failure under `ErrorPolicy::Abort` ended the run
+    pub aborted: bool,
+}
+
+impl Manifest {
+    pub fn keypoint_total(&self) -> usize {
+        self.completed.iter().map(|entry| entry.keypoints).sum()
+    }
+
+    /// Process exit code for the run: nonzero if any image failed, whatever the error policy.
+    pub fn exit_code(&self) -> i32 {
+        if self.failed.is_empty() { 0 } else { 1 }
+    }
+
+    pub fn to_file(&self, path: impl AsRef<Path>) -> Result<(), SuperPointError> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| SuperPointError::Io(std::io::Error::other(e)))?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+/// Every file directly inside `input_dir` with a supported image extension, sorted by path.
+pub fn image_paths(input_dir: &Path) -> Result<Vec<PathBuf>, SuperPointError> {
+    let extensions = preprocessing::supported_formats();
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(input_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| extensions.contains(&ext.to_lowercase().as_str()))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Pairs each of the sorted `inputs` with `<output_dir>/<stem>.png`. Inputs sharing a stem
+/// (`a.jpg`, `a.png`) would write the same file; they are logged and resolved per `policy`.
+pub fn plan_outputs(inputs: Vec<PathBuf>, output_dir: &Path, policy: CollisionPolicy) -> Vec<BatchItem> {
+    let output_for = |stem: &str| output_dir.join(format!("{}.png", stem));
+
+    let mut items: Vec<BatchItem> = Vec::with_capacity(inputs.len());
+    let mut claimed: HashMap<PathBuf, usize> = HashMap::new();
+    for input in inputs {
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let output = output_for(&stem);
+
+        let Some(&previous) = claimed.get(&output) else {
+            claimed.insert(output.clone(), items.len());
+            items.push(BatchItem { input, output });
+            continue;
+        };
+
+        let earlier = items[previous].input.display().to_string();
+        match policy {
+            CollisionPolicy::Overwrite => {
+                warn!("{} overwrites the output of {}", input.display(), earlier);
+                items[previous].input = input;
+            }
+            CollisionPolicy::Skip => {
+                warn!("Skipping {}: {} is already written by {}", input.display(), output.display(), earlier);
+            }
+            CollisionPolicy::Suffix => {
+                let output = (1..)
+                    .map(|n| output_for(&format!("{}_{}", stem, n)))
+                    .find(|candidate| !claimed.contains_key(candidate))
+                    .unwrap();
+                warn!("{} collides with {}; writing {}", input.display(), earlier, output.display());
+                claimed.insert(output.clone(), items.len());
+                items.push(BatchItem { input, output });
+            }
+        }
+    }
+
+    items
+}
+
+/// Runs `process` over `items` on a pool of `jobs` threads; it returns the image's keypoint
+/// count. Failures are logged and recorded; under `ErrorPolicy::Abort` the first one also stops
+/// new images from starting. Once `stop` is set no new image is started either. Either way,
+/// images already in flight finish and are recorded.
+pub fn run_batch<F>(
+    items: &[BatchItem],
+    jobs: usize,
+    on_error: ErrorPolicy,
+    stop: &AtomicBool,
+    process: F,
+) -> Result<Manifest, SuperPointError>
+where
+    F: Fn(&BatchItem) -> Result<usize, SuperPointError> + Sync,
+{
+    run_distributed(items, &[()], jobs, on_error, stop, |_, item| process(item))
+}
+
+/// `run_batch` spread over several workers, e.g. one `Detector` per GPU: image `i` goes to
+/// `workers[i % workers.len()]`, and each worker gets its own thread running a pool of `jobs`
+/// threads, so the workers make progress independently of each other.
+pub fn run_distributed<W, F>(
+    items: &[BatchItem],
+    workers: &[W],
+    jobs: usize,
+    on_error: ErrorPolicy,
+    stop: &AtomicBool,
+    process: F,
+) -> Result<Manifest, SuperPointError>
+where
+    W: Sync,
+    F: Fn(&W, &BatchItem) -> Result<usize, SuperPointError> + Sync,
+{
+    if workers.is_empty() {
+        return Err(SuperPointError::Config("A batch run needs at least one worker".to_string()));
+    }
+
+    let pools = workers
+        .iter()
+        .map(|_| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(jobs.max(1))
+                .build()
+                .map_err(|e| SuperPointError::Config(format!("Failed to start {} worker threads: {}", jobs, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let aborted = AtomicBool::new(false);
+    let run = |worker: usize, item: &BatchItem| {
+        if stop.load(Ordering::SeqCst) || aborted.load(Ordering::SeqCst) {
+            return None;
+        }
+        let outcome = process(&workers[worker], item);
+        if outcome.is_err() && on_error == ErrorPolicy::Abort {
+            aborted.store(true, Ordering::SeqCst);
+        }
+        Some(outcome)
+    };
+
+    let mut outcomes: Vec<Option<Result<usize, SuperPointError>>> = items.iter().map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = pools
+            .iter()
+            .enumerate()
+            .map(|(worker, pool)| {
+                let assigned: Vec<usize> = (worker..items.len()).step_by(workers.len()).collect();
+                scope.spawn(move || {
+                    pool.install(|| {
+                        assigned
+                            .par_iter()
+                            .map(|&index| (index, run(worker, &items[index])))
+                            .collect::<Vec<_>>()
+                    })
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let finished = handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            for (index, outcome) in finished {
+                outcomes[index] = outcome;
+            }
+        }
+    });
+
+    let mut manifest = Manifest {
+        aborted: aborted.into_inner(),
+        ..Manifest::default()
+    };
+    for (index, (item, outcome)) in items.iter().zip(outcomes).enumerate() {
+        match outcome {
+            Some(Ok(keypoints)) => {
+                info!("{}: {} keypoints", item.input.display(), keypoints);
+                manifest.completed.push(ManifestEntry {
+                    input: item.input.clone(),
+                    output: item.output.clone(),
+                    keypoints,
+                    worker: index % workers.len(),
+                });
+            }
+            Some(Err(e)) => {
+                match on_error {
+                    ErrorPolicy::Skip => warn!("Skipping {}: {}", item.input.display(), e),
+                    ErrorPolicy::Abort => error!("Aborting after {}: {}", item.input.display(), e),
+                }
+                manifest.failed.push(FailedEntry {
+                    input: item.input.clone(),
+                    error: e.to_string(),
+                });
+            }
+            None => manifest.interrupted |= !manifest.aborted,
+        }
+    }
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn items(names: &[&str]) -> Vec<BatchItem> {
+        names
+            .iter()
+            .map(|name| BatchItem {
+                input: PathBuf::from("in").join(name),
+                output: PathBuf::from("out").join(name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interrupt_after_first_image_writes_partial_manifest() {
+        let stop = AtomicBool::new(false);
+        let batch = items(&["a.png", "b.png", "c.png"]);
+
+        let manifest = run_batch(&batch, 1, ErrorPolicy::Skip, &stop, |_| {
+            stop.store(true, Ordering::SeqCst);
+            Ok(42)
+        })
+        .unwrap();
+
+        let path = std::env::temp_dir().join(format!("superpoint-{}-{}", std::process::id(), MANIFEST_FILE));
+        manifest.to_file(&path).unwrap();
+        let saved: Manifest = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(saved.interrupted);
+        assert!(saved.failed.is_empty());
+        assert_eq!(
+            saved.completed,
+            vec![ManifestEntry {
+                input: batch[0].input.clone(),
+                output: batch[0].output.clone(),
+                keypoints: 42,
+                worker: 0,
+            }]
+        );
+    }
+
+    /// Runs a batch over `a.jpg` and `a.png` whose "annotation" is the input file name, and
+    /// returns the output directory's contents as `(file name, contents)`.
+    fn colliding_outputs(policy: CollisionPolicy) -> Vec<(String, String)> {
+        let output_dir = std::env::temp_dir().join(format!("superpoint-{}-collision-{:?}", std::process::id(), policy));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let inputs = vec![PathBuf::from("in/a.jpg"), PathBuf::from("in/a.png"), PathBuf::from("in/b.png")];
+
+        let batch = plan_outputs(inputs, &output_dir, policy);
+        run_batch(&batch, 2, ErrorPolicy::Skip, &AtomicBool::new(false), |item| {
+            std::fs::write(&item.output, item.input.file_name().unwrap().to_string_lossy().as_bytes())?;
+            Ok(0)
+        })
+        .unwrap();
+
+        let mut outputs: Vec<(String, String)> = std::fs::read_dir(&output_dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, std::fs::read_to_string(&path).unwrap())
+            })
+            .collect();
+        outputs.sort();
+        std::fs::remove_dir_all(&output_dir).unwrap();
+        outputs
+    }
+
+    fn named(outputs: &[(&str, &str)]) -> Vec<(String, String)> {
+        outputs.iter().map(|&(name, input)| (name.to_string(), input.to_string())).collect()
+    }
+
+    #[test]
+    fn overwrite_keeps_the_last_colliding_input() {
+        assert_eq!(
+            colliding_outputs(CollisionPolicy::Overwrite),
+            named(&[("a.png", "a.png"), ("b.png", "b.png")])
+        );
+    }
+
+    #[test]
+    fn skip_keeps_the_first_colliding_input() {
+        assert_eq!(
+            colliding_outputs(CollisionPolicy::Skip),
+            named(&[("a.png", "a.jpg"), ("b.png", "b.png")])
+        );
+    }
+
+    #[test]
+    fn suffix_writes_every_colliding_input() {
+        assert_eq!(
+            colliding_outputs(CollisionPolicy::Suffix),
+            named(&[("a.png", "a.jpg"), ("a_1.png", "a.png"), ("b.png", "b.png")])
+        );
+    }
+
+    /// `a.png` and `c.png` are valid 4x4 images and `b.png` is corrupt; processing decodes each.
+    fn run_with_corrupt_file(on_error: ErrorPolicy) -> Manifest {
+        let input_dir = std::env::temp_dir().join(format!("superpoint-{}-corrupt-{:?}", std::process::id(), on_error));
+        std::fs::create_dir_all(&input_dir).unwrap();
+        image::RgbImage::new(4, 4).save(input_dir.join("a.png")).unwrap();
+        std::fs::write(input_dir.join("b.png"), b"not a png").unwrap();
+        image::RgbImage::new(4, 4).save(input_dir.join("c.png")).unwrap();
+
+        let batch = plan_outputs(image_paths(&input_dir).unwrap(), Path::new("out"), CollisionPolicy::Suffix);
+        let manifest = run_batch(&batch, 1, on_error, &AtomicBool::new(false), |item| {
+            image::open(&item.input)?;
+            Ok(1)
+        })
+        .unwrap();
+
+        std::fs::remove_dir_all(&input_dir).unwrap();
+        manifest
+    }
+
+    fn completed_names(manifest: &Manifest) -> Vec<String> {
+        manifest
+            .completed
+            .iter()
+            .map(|entry| entry.input.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn skip_policy_continues_past_corrupt_file_but_exits_nonzero() {
+        let manifest = run_with_corrupt_file(ErrorPolicy::Skip);
+
+        assert_eq!(completed_names(&manifest), vec!["a.png", "c.png"]);
+        assert_eq!(manifest.failed.len(), 1);
+        assert!(!manifest.aborted);
+        assert_eq!(manifest.exit_code(), 1);
+    }
+
+    #[test]
+    fn abort_policy_stops_at_corrupt_file() {
+        let manifest = run_with_corrupt_file(ErrorPolicy::Abort);
+
+        assert_eq!(completed_names(&manifest), vec!["a.png"]);
+        assert!(manifest.failed[0].input.ends_with("b.png"));
+        assert!(manifest.aborted);
+        assert!(!manifest.interrupted);
+        assert_eq!(manifest.exit_code(), 1);
+    }
+
+    #[test]
+    fn clean_run_exits_zero() {
+        let batch = items(&["a.png", "b.png"]);
+
+        let manifest = run_batch(&batch, 2, ErrorPolicy::Abort, &AtomicBool::new(false), |_| Ok(3)).unwrap();
+
+        assert_eq!(manifest.keypoint_total(), 6);
+        assert_eq!(manifest.exit_code(), 0);
+    }
+
+    #[test]
+    fn distributed_run_round_robins_across_worker_threads() {
+        let output_dir = std::env::temp_dir().join(format!("superpoint-{}-devices", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let inputs = (0..5).map(|i| PathBuf::from(format!("in/{}.png", i))).collect();
+        let batch = plan_outputs(inputs, &output_dir, CollisionPolicy::Suffix);
+        let devices = ["cpu:0", "cpu:1"];
+        let threads = std::sync::Mutex::new(vec![HashSet::new(); devices.len()]);
+
+        let manifest = run_distributed(&batch, &devices, 1, ErrorPolicy::Skip, &AtomicBool::new(false), |device, item| {
+            let worker = devices.iter().position(|d| d == device).unwrap();
+            threads.lock().unwrap()[worker].insert(std::thread::current().id());
+            std::fs::write(&item.output, device)?;
+            Ok(1)
+        })
+        .unwrap();
+
+        let workers: Vec<usize> = manifest.completed.iter().map(|entry| entry.worker).collect();
+        assert_eq!(workers, vec![0, 1, 0, 1, 0]);
+        for entry in &manifest.completed {
+            assert_eq!(std::fs::read_to_string(&entry.output).unwrap(), devices[entry.worker]);
+        }
+        let threads = threads.into_inner().unwrap();
+        assert!(threads[0].is_disjoint(&threads[1]));
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
@@ -1,4 +1,6 @@
+use crate::error::SuperPointError;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,26 +9,231 @@ pub struct Config {
     pub image: ImageConfig,
     pub keypoint: KeypointConfig,
     pub visualization: VisualizationConfig,
+    #[serde(default)]
+    pub export: ExportConfig,
+    #[serde(default)]
+    pub detection: DetectionConfig,
+    #[serde(default)]
+    pub tiling: Option<TilingConfig>,
+    #[serde(default)]
+    pub camera: Option<CameraConfig>,
+    #[serde(default)]
+    pub batch: BatchConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
     pub path: PathBuf,
     pub use_cuda: bool,
+    /// Backend to run on; `Auto` tries CUDA (if `use_cuda`), then MPS, then CPU
+    #[serde(default)]
+    pub device_preference: DevicePreference,
+    /// Weight/activation precision for the forward pass; `Float16` needs CUDA or MPS
+    #[serde(default)]
+    pub precision: Precision,
+    /// Retry a single inference on CPU when CUDA runs out of memory
+    #[serde(default)]
+    pub oom_fallback_cpu: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageConfig {
     pub width: i64,
     pub height: i64,
+    /// Legacy switch: `true` = `ZeroToOne`, `false` = `Raw`. Ignored when `normalization` is set
     pub normalize: bool,
+    #[serde(default)]
+    pub normalization: Option<NormalizationMode>,
+    #[serde(default)]
+    pub grayscale_mode: GrayscaleMode,
+    /// Stage the input tensor in page-locked memory for async CUDA uploads (ignored on CPU)
+    #[serde(default)]
+    pub pin_memory: bool,
+    /// How images whose aspect ratio differs from `width`/`height` are fitted to the model input
+    #[serde(default)]
+    pub aspect_mode: AspectMode,
+    /// Gray level of the `Letterbox` padding
+    #[serde(default)]
+    pub letterbox_fill: u8,
+    /// Resize filter used to fit images (and pyramid levels) to the model input
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+impl ImageConfig {
+    /// The pixel transform in effect, falling back to the legacy `normalize` flag.
+    pub fn normalization_mode(&self) -> NormalizationMode {
+        match self.normalization {
+            Some(mode) => mode,
+            None if self.normalize => NormalizationMode::ZeroToOne,
+            None => NormalizationMode::Raw,
+        }
+    }
+}
+
+/// How 8-bit gray levels are mapped to model input values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NormalizationMode {
+    /// `v / 255`; what the reference `superpoint_v2.pt` export was trained on
+    #[default]
+    ZeroToOne,
+    /// `v / 127.5 - 1`
+    MinusOneToOne,
+    /// Unscaled `0..=255`
+    Raw,
+    /// `(v / 255 - mean) / std` with the ImageNet RGB statistics averaged over channels
+    ImageNet,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AspectMode {
+    /// Resize straight to `width` x `height`, distorting other aspect ratios
+    #[default]
+    Stretch,
+    /// Scale to fit inside `width` x `height` and pad the rest with `letterbox_fill`
+    Letterbox,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    Nearest,
+    /// Bilinear
+    Triangle,
+    /// Bicubic
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precision {
+    #[default]
+    Float32,
+    /// Half precision; heads are cast back to f32 before softmax
+    Float16,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DevicePreference {
+    #[default]
+    Auto,
+    Cuda,
+    /// Apple Silicon GPU
+    Mps,
+    Cpu,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GrayscaleMode {
+    /// `image`'s built-in luma conversion (Rec. 709 weights)
+    #[default]
+    Luma,
+    /// OpenCV `COLOR_RGB2GRAY` fixed-point arithmetic, as used by the reference SuperPoint pipeline
+    OpenCvCompat,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeypointConfig {
     pub threshold: f64,
+    #[serde(default)]
+    pub threshold_mode: ThresholdMode,
     pub max_keypoints: Option<usize>,
+    /// Ignore `threshold` and keep about this many of the strongest responses instead
+    #[serde(default)]
+    pub top_k: Option<usize>,
     pub nms_radius: Option<f32>,
+    /// Which suppression runs on the candidates; `Radius` uses `nms_radius`
+    #[serde(default)]
+    pub nms_mode: NmsMode,
+    /// Cell size (in pixels) for `NmsMode::Grid`
+    #[serde(default = "default_grid_nms_cell_size")]
+    pub grid_nms_cell_size: u32,
+    /// Keypoints kept per cell by `NmsMode::Grid`
+    #[serde(default = "default_grid_nms_max_per_cell")]
+    pub grid_nms_max_per_cell: usize,
+    /// Largest equal-score plateau (in pixels) accepted as a single peak
+    #[serde(default)]
+    pub max_plateau_size: Option<usize>,
+    #[serde(default)]
+    pub plateau_handling: PlateauHandling,
+    /// Radius of a second NMS pass over the final, original-space keypoints (after tile or
+    /// pyramid merging)
+    #[serde(default)]
+    pub final_nms_radius: Option<f32>,
+    /// Divide the heatmap by its local mean over this window (odd, in pixels) before thresholding
+    #[serde(default)]
+    pub local_contrast_window: Option<i64>,
+    /// Treat the input as a 360° equirectangular frame: NMS wraps across the left/right seam
+    #[serde(default)]
+    pub equirectangular: bool,
+    /// In equirectangular mode, drop keypoints beyond this absolute latitude in degrees
+    #[serde(default)]
+    pub pole_cutoff: Option<f32>,
+    /// Threshold the heatmap in blocks of this many rows to cap peak memory on large inputs
+    #[serde(default)]
+    pub block_rows: Option<i64>,
+    /// Drop candidates within this many pixels of the model-space image boundary
+    #[serde(default)]
+    pub border: Option<u32>,
+    /// Drop weak candidates on 8-pixel cell corners, where depth-to-space leaves a grid artifact
+    #[serde(default)]
+    pub suppress_grid_artifacts: bool,
+    /// Grayscale region-of-interest mask, aligned with the input image; keypoints on mask
+    /// pixels below 128 are discarded. Not supported together with `[tiling]`
+    #[serde(default)]
+    pub mask: Option<PathBuf>,
+    /// Original-image rectangles `[x, y, width, height]` whose keypoints are discarded
+    #[serde(default)]
+    pub exclude_regions: Vec<[u32; 4]>,
+    /// How `max_keypoints` picks the survivors
+    #[serde(default)]
+    pub selection_policy: KeypointSelectionPolicy,
+    /// Grid cell size (in pixels) used by `KeypointSelectionPolicy::Coverage`
+    #[serde(default = "default_coverage_cell_size")]
+    pub coverage_cell_size: u32,
+    /// Drop keypoints whose sampled descriptor norm (before normalization) is below this
+    #[serde(default)]
+    pub min_descriptor_norm: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ThresholdMode {
+    /// `threshold` is compared directly against heatmap probabilities
+    #[default]
+    Absolute,
+    /// `threshold` is a quantile in [0, 1] of each image's heatmap values
+    Percentile,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NmsMode {
+    /// Greedy suppression within `nms_radius`
+    #[default]
+    Radius,
+    /// Keep the top `grid_nms_max_per_cell` keypoints of each grid cell
+    Grid,
+    /// No suppression
+    None,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeypointSelectionPolicy {
+    /// Keep the `max_keypoints` highest-scoring keypoints
+    #[default]
+    TopScore,
+    /// Keep the best keypoint of every non-empty grid cell first, then fill the remaining
+    /// budget with the highest-scoring leftovers
+    Coverage,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlateauHandling {
+    /// Drop every keypoint on an oversized plateau
+    #[default]
+    Reject,
+    /// Replace an oversized plateau with a single keypoint at its centroid
+    Collapse,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +241,216 @@ pub struct VisualizationConfig {
     pub circle_radius: u32,
     pub circle_color: [u8; 3],
     pub line_thickness: u32,
+    #[serde(default)]
+    pub score_transform: ScoreTransform,
+    /// Draw a cross over each keypoint circle
+    #[serde(default = "default_true")]
+    pub draw_cross: bool,
+    /// Outline each keypoint's NMS suppression radius (debug aid)
+    #[serde(default)]
+    pub draw_nms_radius: bool,
+    /// Append a colorbar with min/max labels to heatmap visualizations
+    #[serde(default)]
+    pub draw_colorbar: bool,
+    /// Color ramp used for heatmap visualizations and their colorbar
+    #[serde(default)]
+    pub colormap: Colormap,
+    /// Draw keypoints over a dimmed grayscale copy of the image instead of the original colors
+    #[serde(default)]
+    pub grayscale_background: bool,
+    /// Opacity of the grayscale backdrop over black (1.0 = undimmed)
+    #[serde(default = "default_background_alpha")]
+    pub background_alpha: f32,
+}
+
+fn default_background_alpha() -> f32 {
+    0.5
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_coverage_cell_size() -> u32 {
+    32
+}
+
+fn default_grid_nms_cell_size() -> u32 {
+    16
+}
+
+fn default_grid_nms_max_per_cell() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScoreTransform {
+    #[default]
+    Linear,
+    /// Square root, spreading out the low end of the score range
+    Sqrt,
+    /// `log10(1 + 9s)`, mapping [0, 1] onto [0, 1] with a stronger boost than `Sqrt`
+    Log,
+}
+
+impl ScoreTransform {
+    pub fn apply(self, score: f32) -> f32 {
+        let score = score.clamp(0.0, 1.0);
+        match self {
+            ScoreTransform::Linear => score,
+            ScoreTransform::Sqrt => score.sqrt(),
+            ScoreTransform::Log => (1.0 + 9.0 * score).log10(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Colormap {
+    /// Blue -> green -> red
+    #[default]
+    BlueGreenRed,
+    /// MATLAB-style dark blue -> cyan -> yellow -> dark red
+    Jet,
+    /// Perceptually uniform dark purple -> teal -> yellow
+    Viridis,
+    Grayscale,
+    /// Google's improved rainbow, smoother than `Jet`
+    Turbo,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportConfig {
+    /// Decimal places kept for x/y/score in serialized keypoints (None = full precision)
+    pub coord_precision: Option<usize>,
+    #[serde(default)]
+    pub descriptor_encoding: DescriptorEncoding,
+    #[serde(default)]
+    pub sort_order: SortOrder,
+    /// Sample a descriptor for every keypoint so exports carry it (costs a descriptor-head readout)
+    #[serde(default)]
+    pub include_descriptors: bool,
+}
+
+/// Settings for `--input-dir` runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchConfig {
+    /// What to do when several inputs map to the same `<stem>.png` output
+    #[serde(default)]
+    pub on_collision: CollisionPolicy,
+    /// Whether an image that fails to load, detect or save stops the run
+    #[serde(default)]
+    pub on_error: ErrorPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CollisionPolicy {
+    /// The last input in path order wins; earlier inputs with the same stem are not processed
+    Overwrite,
+    /// The first input in path order wins; later inputs with the same stem are skipped
+    Skip,
+    /// Later inputs get `<stem>_1.png`, `<stem>_2.png`, ... so nothing is clobbered
+    #[default]
+    Suffix,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorPolicy {
+    /// Log the failure, record it in the manifest and carry on; the exit code is still nonzero
+    #[default]
+    Skip,
+    /// Start no further images after the first failure
+    Abort,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectionConfig {
+    /// Factors of the model input size to run at; several values enable pyramid detection
+    pub scales: Vec<f32>,
+    /// How the scores of keypoints merged across pyramid levels are combined
+    #[serde(default)]
+    pub score_aggregation: ScoreAggregation,
+    /// Gaussian-blur the image before shrinking it to each level, so detail finer than the
+    /// level's resolution is removed instead of aliasing into spurious corners
+    #[serde(default)]
+    pub pre_blur: bool,
+}
+
+impl DetectionConfig {
+    pub fn is_multi_scale(&self) -> bool {
+        self.scales.iter().any(|&scale| scale != 1.0)
+    }
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            scales: vec![1.0],
+            score_aggregation: ScoreAggregation::default(),
+            pre_blur: false,
+        }
+    }
+}
+
+/// Combines the scores of keypoints detected at the same spot on several pyramid levels.
+/// Merging happens after each level was thresholded, so `Mean` never drops a merged keypoint
+/// below the threshold it already passed, while `Sum` can exceed 1.0 and favours keypoints
+/// seen on many levels when `max_keypoints` truncates the merged set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScoreAggregation {
+    /// Strongest level's score
+    #[default]
+    Max,
+    Mean,
+    Sum,
+}
+
+/// Detect on overlapping full-resolution tiles instead of one downscaled image
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TilingConfig {
+    /// Tile size in original-image pixels; each tile is resized to the model input
+    pub tile_width: u32,
+    pub tile_height: u32,
+    /// Pixels shared by neighbouring tiles
+    pub overlap: u32,
+}
+
+/// Pinhole calibration of the source camera, used to undistort keypoints
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CameraConfig {
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+    /// OpenCV-ordered `[k1, k2, p1, p2, k3]`
+    #[serde(default)]
+    pub distortion: [f32; 5],
+}
+
+impl CameraConfig {
+    /// `[fx, fy, cx, cy]`, as taken by `geometry::undistort_keypoints`
+    pub fn intrinsics(&self) -> [f32; 4] {
+        [self.fx, self.fy, self.cx, self.cy]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    /// Highest score first
+    #[default]
+    ScoreDesc,
+    /// Top-to-bottom, left-to-right by rounded pixel position
+    RasterOrder,
+    /// Keep the detection order
+    None,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DescriptorEncoding {
+    /// Plain array of numbers
+    #[default]
+    Array,
+    /// Base64 of the little-endian f32 bytes (requires the `base64` feature)
+    Base64,
 }
 
 impl Default for Config {
@@ -42,22 +459,62 @@ impl Default for Config {
             model: ModelConfig {
                 path: PathBuf::from("./superpoint_v2.pt"),
                 use_cuda: true,
+                device_preference: DevicePreference::default(),
+                precision: Precision::default(),
+                oom_fallback_cpu: false,
             },
             image: ImageConfig {
                 width: 320,
                 height: 240,
                 normalize: true,
+                normalization: None,
+                grayscale_mode: GrayscaleMode::default(),
+                pin_memory: false,
+                aspect_mode: AspectMode::default(),
+                letterbox_fill: 0,
+                interpolation: Interpolation::default(),
             },
             keypoint: KeypointConfig {
                 threshold: 0.05,
+                threshold_mode: ThresholdMode::default(),
                 max_keypoints: Some(1000),
+                top_k: None,
                 nms_radius: Some(4.0),
+                nms_mode: NmsMode::default(),
+                grid_nms_cell_size: default_grid_nms_cell_size(),
+                grid_nms_max_per_cell: default_grid_nms_max_per_cell(),
+                max_plateau_size: None,
+                plateau_handling: PlateauHandling::default(),
+                final_nms_radius: None,
+                local_contrast_window: None,
+                equirectangular: false,
+                pole_cutoff: None,
+                block_rows: None,
+                border: None,
+                suppress_grid_artifacts: false,
+                mask: None,
+                exclude_regions: Vec::new(),
+                selection_policy: KeypointSelectionPolicy::default(),
+                coverage_cell_size: default_coverage_cell_size(),
+                min_descriptor_norm: None,
             },
             visualization: VisualizationConfig {
                 circle_radius: 3,
                 circle_color: [255, 0, 0],
                 line_thickness: 2,
+                score_transform: ScoreTransform::default(),
+                draw_cross: true,
+                draw_nms_radius: false,
+                draw_colorbar: false,
+                colormap: Colormap::default(),
+                grayscale_background: false,
+                background_alpha: default_background_alpha(),
             },
+            export: ExportConfig::default(),
+            detection: DetectionConfig::default(),
+            tiling: None,
+            camera: None,
+            batch: BatchConfig::default(),
         }
     }
 }
@@ -74,4 +531,104 @@ impl Config {
         std::fs::write(path, content)?;
         Ok(())
     }
-} 
\ No newline at end of file
+    
+    /// Rejects values that would otherwise fail confusingly downstream, naming the offending field.
+    pub fn validate(&self) -> Result<(), SuperPointError> {
+        let invalid = |field: &str, value: &dyn std::fmt::Display, expected: &str| {
+            Err(SuperPointError::Config(format!("Invalid {} = {}: expected {}", field, value, expected)))
+        };
+        
+        if !(0.0..=1.0).contains(&self.keypoint.threshold) {
+            return invalid("keypoint.threshold", &self.keypoint.threshold, "a value in [0, 1]");
+        }
+        
+        for (field, value) in [("image.width", self.image.width), ("image.height", self.image.height)] {
+            if value <= 0 {
+                return invalid(field, &value, "a positive size");
+            }
+        }
+        
+        for (field, radius) in [
+            ("keypoint.nms_radius", self.keypoint.nms_radius),
+            ("keypoint.final_nms_radius", self.keypoint.final_nms_radius),
+        ] {
+            if let Some(radius) = radius
+                && (radius.is_nan() || radius < 0.0)
+            {
+                return invalid(field, &radius, "a non-negative radius");
+            }
+        }
+        
+        // Tiles are detected independently, so a whole-image mask can't be mapped onto them
+        if self.tiling.is_some() && self.keypoint.mask.is_some() {
+            return Err(SuperPointError::Config(
+                "keypoint.mask cannot be combined with [tiling]".to_string(),
+            ));
+        }
+        
+        if self.visualization.circle_radius == 0 {
+            return invalid("visualization.circle_radius", &0, "a non-zero radius");
+        }
+        
+        if cfg!(not(feature = "base64")) && self.export.descriptor_encoding == DescriptorEncoding::Base64 {
+            return Err(SuperPointError::Config(
+                "export.descriptor_encoding = \"Base64\" requires building with the `base64` feature".to_string(),
+            ));
+        }
+        
+        Ok(())
+    }
+    
+    /// Lists every field that differs between `self` and `other` as
+    /// `(field_path, self_value, other_value)`, e.g. `("keypoint.threshold", "0.05", "0.1")`.
+    /// Unset optional fields are reported as `None`.
+    pub fn diff(&self, other: &Config) -> Vec<(String, String, String)> {
+        let (Ok(lhs), Ok(rhs)) = (toml::Value::try_from(self), toml::Value::try_from(other)) else {
+            return Vec::new();
+        };
+        
+        let mut differences = Vec::new();
+        diff_values("", Some(&lhs), Some(&rhs), &mut differences);
+        differences
+    }
+}
+
+fn diff_values(
+    path: &str,
+    lhs: Option<&toml::Value>,
+    rhs: Option<&toml::Value>,
+    differences: &mut Vec<(String, String, String)>,
+) {
+    match (lhs, rhs) {
+        (Some(toml::Value::Table(lhs)), Some(toml::Value::Table(rhs))) => {
+            let keys: BTreeSet<&String> = lhs.keys().chain(rhs.keys()).collect();
+            for key in keys {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                diff_values(&child, lhs.get(key), rhs.get(key), differences);
+            }
+        }
+        _ if lhs != rhs => {
+            let display = |value: Option<&toml::Value>| value.map_or("None".to_string(), |v| v.to_string());
+            differences.push((path.to_string(), display(lhs), display(rhs)));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn diff_reports_only_the_changed_field() {
+        let default = Config::default();
+        let mut changed = default.clone();
+        changed.keypoint.threshold = 0.2;
+        
+        assert_eq!(
+            default.diff(&changed),
+            vec![("keypoint.threshold".to_string(), "0.05".to_string(), "0.2".to_string())]
+        );
+        assert!(default.diff(&default).is_empty());
+    }
+}
@@ -0,0 +1,694 @@
+use crate::config::{Config, ImageConfig};
+use crate::error::SuperPointError;
+use crate::geometry::{self, ModelDims, OriginalDims};
+use crate::keypoint::Keypoint;
+use crate::model::SuperPointModel;
+use crate::postprocessing::{compute_scale_factors, KeypointExtractor};
+use crate::preprocessing::ImagePreprocessor;
+use crate::report::Report;
+use crate::visualization::Visualizer;
+use image::{DynamicImage, GenericImageView, RgbImage};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tch::{Cuda, Device, Tensor};
+
+/// Duplicate-suppression radius (model pixels) for tile/pyramid merging when `nms_radius` is unset.
+const MERGE_RADIUS: f32 = 2.0;
+
+/// Everything produced by a single pass of the pipeline.
+pub struct DetectionOutput {
+    /// Keypoints scaled to the original image
+    pub keypoints: Vec<Keypoint>,
+    /// Keypoints in model input coordinates, before scaling
+    pub keypoints_model_space: Vec<Keypoint>,
+    /// Full-resolution `[H, W]` probability heatmap on the model device
+    pub heatmap: Tensor,
+    pub device: Device,
+    pub timings: Timings,
+}
+
+/// Wall-clock time spent in each pipeline stage. `Detector` fills everything up to scaling;
+/// `visualization` is left for callers that draw the result.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    pub preprocessing: Duration,
+    /// Measured after synchronizing the CUDA device, so queued kernels are included
+    pub inference: Duration,
+    pub extraction: Duration,
+    /// Scaling to the original image plus the optional final NMS
+    pub scaling: Duration,
+    pub visualization: Duration,
+}
+
+impl std::ops::AddAssign for Timings {
+    fn add_assign(&mut self, other: Self) {
+        self.preprocessing += other.preprocessing;
+        self.inference += other.inference;
+        self.extraction += other.extraction;
+        self.scaling += other.scaling;
+        self.visualization += other.visualization;
+    }
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.preprocessing + self.inference + self.extraction + self.scaling + self.visualization
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages = [
+            ("Preprocessing:", self.preprocessing),
+            ("Inference:", self.inference),
+            ("Extraction:", self.extraction),
+            ("Scaling:", self.scaling),
+            ("Visualization:", self.visualization),
+        ];
+        for (label, duration) in stages {
+            writeln!(f, "  {:<14} {:>9.2} ms", label, duration.as_secs_f64() * 1000.0)?;
+        }
+        write!(f, "  {:<14} {:>9.2} ms", "Total:", self.total().as_secs_f64() * 1000.0)
+    }
+}
+
+/// Owns every pipeline stage so images can be processed without touching the filesystem.
+pub struct Detector {
+    model: SuperPointModel,
+    preprocessor: ImagePreprocessor,
+    extractor: KeypointExtractor,
+    visualizer: Visualizer,
+    config: Config,
+}
+
+impl Detector {
+    pub fn new(config: &Config) -> Result<Self, SuperPointError> {
+        Self::with_model(config, SuperPointModel::new(config)?)
+    }
+
+    /// Like `new`, with the model loaded onto `device` (see `SuperPointModel::new_on_device`).
+    pub fn new_on_device(config: &Config, device: Device) -> Result<Self, SuperPointError> {
+        Self::with_model(config, SuperPointModel::new_on_device(config, device)?)
+    }
+
+    fn with_model(config: &Config, model: SuperPointModel) -> Result<Self, SuperPointError> {
+        let preprocessor = ImagePreprocessor::new(config.image.clone(), model.device());
+
+        let mut extractor = KeypointExtractor::new(config.keypoint.clone());
+        if let Some(mask_path) = &config.keypoint.mask {
+            extractor = extractor.with_mask(preprocessor.load_mask(mask_path)?);
+        }
+
+        Ok(Self {
+            model,
+            preprocessor,
+            extractor,
+            visualizer: Visualizer::new(config.visualization.clone()),
+            config: config.clone(),
+        })
+    }
+
+    pub fn device(&self) -> Device {
+        self.model.device()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The extractor used by every `detect_*` method, including any region-of-interest mask.
+    pub fn extractor(&self) -> &KeypointExtractor {
+        &self.extractor
+    }
+
+    pub fn visualizer(&self) -> &Visualizer {
+        &self.visualizer
+    }
+
+    /// See `SuperPointModel::warmup`.
+    pub fn warmup(&self) -> Result<(), SuperPointError> {
+        self.model.warmup()
+    }
+
+    /// Loads an image from disk and runs `detect_image` on it.
+    pub fn detect(&self, path: impl AsRef<Path>) -> Result<Vec<Keypoint>, SuperPointError> {
+        let path = path.as_ref();
+        let image = image::open(path).map_err(|e| {
+            SuperPointError::ImageProcessing(format!("Failed to load image '{}': {}", path.display(), e))
+        })?;
+
+        self.detect_image(&image)
+    }
+
+    /// Runs the full pipeline and returns keypoints scaled to the original image.
+    /// Uses `detect_tiled` when a `[tiling]` section is configured, otherwise `detect_pyramid`
+    /// when several `detection.scales` are set.
+    pub fn detect_image(&self, image: &DynamicImage) -> Result<Vec<Keypoint>, SuperPointError> {
+        Ok(self.detect_timed(image)?.0)
+    }
+
+    /// `detect_image` that also returns the per-stage timings. For tiled and pyramid detection
+    /// they are summed over every tile or level, and merging counts towards `scaling`.
+    pub fn detect_timed(&self, image: &DynamicImage) -> Result<(Vec<Keypoint>, Timings), SuperPointError> {
+        let mut timings = Timings::default();
+        let keypoints = if self.config.tiling.is_some() {
+            self.tiled(image, &mut timings)?
+        } else if self.config.detection.is_multi_scale() {
+            self.pyramid(image, &mut timings)?
+        } else {
+            let output = self.detect_full(image)?;
+            timings = output.timings;
+            output.keypoints
+        };
+
+        Ok((keypoints, timings))
+    }
+
+    /// Detects at full resolution by running the model on overlapping tiles, then merges the
+    /// per-tile keypoints, suppressing duplicates in the overlap bands with radius NMS.
+    pub fn detect_tiled(&self, image: &DynamicImage) -> Result<Vec<Keypoint>, SuperPointError> {
+        self.tiled(image, &mut Timings::default())
+    }
+
+    fn tiled(&self, image: &DynamicImage, timings: &mut Timings) -> Result<Vec<Keypoint>, SuperPointError> {
+        let tiling = self
+            .config
+            .tiling
+            .ok_or_else(|| SuperPointError::Config("Tiled detection requires a [tiling] section".to_string()))?;
+
+        let tiles = self
+            .preprocessor
+            .split_into_tiles(image, tiling.tile_width, tiling.tile_height, tiling.overlap);
+        let per_tile = tiles
+            .into_iter()
+            .map(|(origin, tile)| {
+                let output = self.run_pipeline(&tile)?;
+                *timings += output.timings;
+                Ok((origin, output.keypoints))
+            })
+            .collect::<Result<Vec<_>, SuperPointError>>()?;
+
+        let start = Instant::now();
+
+        // The NMS radius is in model pixels; tiles are resized to the model input. Use the
+        // larger axis scale so duplicates are still caught when tiles are stretched unevenly
+        let tile_dims = OriginalDims::new(tiling.tile_width, tiling.tile_height);
+        let (scale_x, scale_y) = compute_scale_factors(tile_dims, ModelDims::from_config(&self.config.image));
+        let radius = self.config.keypoint.nms_radius.unwrap_or(MERGE_RADIUS) * scale_x.max(scale_y);
+
+        let merged = self.extractor.merge_tiled_keypoints(per_tile, radius);
+        let merged = self.finalize(merged);
+        timings.scaling += start.elapsed();
+
+        Ok(merged)
+    }
+
+    /// Runs the model at every `detection.scales` factor of the configured input size, maps each
+    /// level's keypoints to the original image and merges them with radius NMS. `Keypoint.scale`
+    /// is set to `1 / factor`, so keypoints found on coarser levels report a larger size.
+    pub fn detect_pyramid(&self, image: &DynamicImage) -> Result<Vec<Keypoint>, SuperPointError> {
+        self.pyramid(image, &mut Timings::default())
+    }
+
+    fn pyramid(&self, image: &DynamicImage, timings: &mut Timings) -> Result<Vec<Keypoint>, SuperPointError> {
+        let model_dims = ModelDims::from_config(&self.config.image);
+        let original_dims = OriginalDims::from(image.dimensions());
+        let mut keypoints = Vec::new();
+
+        for &scale in &self.config.detection.scales {
+            if scale <= 0.0 {
+                return Err(SuperPointError::Config(format!("Pyramid scale must be positive, got {}", scale)));
+            }
+
+            // Keep every level on the model's 8-pixel cell grid
+            let level_dim = |dim: i64| ((dim as f32 * scale / 8.0).round() as i64).max(1) * 8;
+            let level_config = ImageConfig {
+                width: level_dim(model_dims.width),
+                height: level_dim(model_dims.height),
+                ..self.config.image.clone()
+            };
+            let level_dims = ModelDims::from_config(&level_config);
+            let preprocessor = ImagePreprocessor::new(level_config, self.device());
+
+            let start = Instant::now();
+            let blurred = anti_alias_sigma(original_dims, level_dims)
+                .filter(|_| self.config.detection.pre_blur)
+                .map(|sigma| image.blur(sigma));
+            let input_tensor = preprocessor.preprocess_image(blurred.as_ref().unwrap_or(image))?;
+            timings.preprocessing += start.elapsed();
+
+            let start = Instant::now();
+            let (heatmap, descriptors) = self.infer(&input_tensor)?;
+            if let Device::Cuda(index) = self.device() {
+                Cuda::synchronize(index as i64);
+            }
+            timings.inference += start.elapsed();
+
+            let start = Instant::now();
+            let mut level = self.extractor.extract_keypoints(&heatmap)?;
+            if let Some(descriptors) = &descriptors {
+                level = self.extractor.attach_descriptors(descriptors, level, level_dims)?;
+            }
+            timings.extraction += start.elapsed();
+
+            let start = Instant::now();
+            let level = self.extractor.scale_keypoints_to_original(
+                level,
+                original_dims,
+                level_dims,
+                preprocessor.letterbox(original_dims),
+            );
+
+            keypoints.extend(level.into_iter().map(|mut kp| {
+                kp.scale = Some(1.0 / scale);
+                kp
+            }));
+            timings.scaling += start.elapsed();
+        }
+
+        let start = Instant::now();
+        // Use the larger axis scale so duplicates are still caught on anisotropic resizes
+        let (scale_x, scale_y) = compute_scale_factors(original_dims, model_dims);
+        let radius = self.config.keypoint.nms_radius.unwrap_or(MERGE_RADIUS) * scale_x.max(scale_y);
+        let merged = self.extractor.merge_overlapping(keypoints, radius, self.config.detection.score_aggregation);
+        let mut merged = self.finalize(merged);
+        if let Some(max_kpts) = self.config.keypoint.max_keypoints {
+            merged.truncate(max_kpts);
+        }
+        timings.scaling += start.elapsed();
+
+        Ok(merged)
+    }
+
+    /// Runs the full pipeline once and returns every intermediate result.
+    pub fn detect_full(&self, image: &DynamicImage) -> Result<DetectionOutput, SuperPointError> {
+        let mut output = self.run_pipeline(image)?;
+        let start = Instant::now();
+        output.keypoints = self.finalize(output.keypoints);
+        output.timings.scaling += start.elapsed();
+
+        Ok(output)
+    }
+
+    /// `detect_full` without the whole-image steps of `finalize`, so tiles can run it too.
+    fn run_pipeline(&self, image: &DynamicImage) -> Result<DetectionOutput, SuperPointError> {
+        let mut timings = Timings::default();
+
+        let start = Instant::now();
+        let input_tensor = self.preprocessor.preprocess_image(image)?;
+        timings.preprocessing = start.elapsed();
+
+        let start = Instant::now();
+        let (heatmap, descriptors) = self.infer(&input_tensor)?;
+        if let Device::Cuda(index) = self.device() {
+            Cuda::synchronize(index as i64);
+        }
+        timings.inference = start.elapsed();
+
+        let (keypoints, keypoints_model_space) =
+            self.postprocess(&heatmap, descriptors.as_ref(), image.dimensions().into(), &mut timings)?;
+
+        Ok(DetectionOutput {
+            keypoints,
+            keypoints_model_space,
+            heatmap,
+            device: self.device(),
+            timings,
+        })
+    }
+
+    /// Runs the full pipeline and summarizes the run (config, input hash, device, timings,
+    /// keypoint statistics) as a serializable `Report`.
+    pub fn detect_report(&self, image: &DynamicImage) -> Result<(Vec<Keypoint>, Report), SuperPointError> {
+        let (keypoints, timings) = self.detect_timed(image)?;
+        let report = Report::from_keypoints(&self.config, image, &keypoints, self.device(), &timings);
+
+        Ok((keypoints, report))
+    }
+
+    /// Detects keypoints in a row-major 8-bit grayscale buffer without decoding an image.
+    pub fn detect_from_gray(
+        &self,
+        buf: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<Keypoint>, SuperPointError> {
+        let input_tensor = self.preprocessor.create_tensor_from_gray_buffer(buf, width, height)?;
+        let heatmap = self.model.infer(&input_tensor)?;
+        let (keypoints, _) =
+            self.postprocess(&heatmap, None, OriginalDims::new(width, height), &mut Timings::default())?;
+
+        Ok(self.finalize(keypoints))
+    }
+
+    /// Splits the keypoints of `heatmap` (from `detect_full` on `image`) into confidence bands,
+    /// highest threshold first, each scaled to the original image. See `extract_bands`.
+    pub fn detect_bands(
+        &self,
+        image: &DynamicImage,
+        heatmap: &Tensor,
+        thresholds: &[f64],
+    ) -> Result<Vec<(f64, Vec<Keypoint>)>, SuperPointError> {
+        let original_dims = OriginalDims::from(image.dimensions());
+        let model_dims = ModelDims::from_config(&self.config.image);
+
+        Ok(self
+            .extractor
+            .extract_bands(heatmap, thresholds)?
+            .into_iter()
+            .map(|(threshold, band)| {
+                let band = self.extractor.scale_keypoints_to_original(
+                    band,
+                    original_dims,
+                    model_dims,
+                    self.preprocessor.letterbox(original_dims),
+                );
+                (threshold, self.extractor.apply_exclusions(band))
+            })
+            .collect())
+    }
+
+    /// The model-space `nms_radius` expressed as `(radius_x, radius_y)` in pixels of an
+    /// `original_size` image, or `None` when radius NMS is not configured.
+    pub fn nms_radius_in_image(&self, original_size: OriginalDims) -> Option<(f32, f32)> {
+        let nms_radius = self.config.keypoint.nms_radius?;
+        let (scale_x, scale_y) = match self.preprocessor.letterbox(original_size) {
+            Some(letterbox) => (1.0 / letterbox.scale, 1.0 / letterbox.scale),
+            None => compute_scale_factors(original_size, ModelDims::from_config(&self.config.image)),
+        };
+
+        Some((nms_radius * scale_x, nms_radius * scale_y))
+    }
+
+    /// Undistorts original-image keypoints with the `[camera]` calibration, if one is configured.
+    /// Apply this before matching or export; drawing should keep the distorted positions.
+    pub fn undistort(&self, keypoints: &[Keypoint]) -> Vec<Keypoint> {
+        match &self.config.camera {
+            Some(camera) => geometry::undistort_keypoints(keypoints, camera.intrinsics(), camera.distortion),
+            None => keypoints.to_vec(),
+        }
+    }
+
+    /// Detects keypoints on many crops in one batched forward pass. Crops are resized to the
+    /// model input size if needed; keypoints are returned in each crop's own coordinates.
+    pub fn detect_crops(&self, crops: &[DynamicImage]) -> Result<Vec<Vec<Keypoint>>, SuperPointError> {
+        if crops.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let inputs = crops
+            .iter()
+            .map(|crop| self.preprocessor.preprocess_image(crop))
+            .collect::<Result<Vec<_>, _>>()?;
+        let heatmaps = self.model.infer_batch(&Tensor::cat(&inputs, 0))?;
+        let model_dims = ModelDims::from_config(&self.config.image);
+
+        Ok(self
+            .extractor
+            .extract_batch(&heatmaps)?
+            .into_iter()
+            .zip(crops)
+            .map(|(keypoints, crop)| {
+                self.extractor.scale_keypoints_to_original(
+                    keypoints,
+                    crop.dimensions().into(),
+                    model_dims,
+                    self.preprocessor.letterbox(crop.dimensions().into()),
+                )
+            })
+            .collect())
+    }
+
+    /// Same as `detect_image`, additionally returning the annotated image in memory.
+    pub fn detect_and_annotate(
+        &self,
+        image: &DynamicImage,
+    ) -> Result<(Vec<Keypoint>, RgbImage), SuperPointError> {
+        let keypoints = self.detect_image(image)?;
+        let annotated = self.visualizer.draw_keypoints_with_scores(image, &keypoints)?;
+
+        Ok((keypoints, annotated))
+    }
+
+    /// Steps that only make sense once per image, on the final original-space keypoints (after
+    /// tile or pyramid merging): `exclude_regions`, then the optional `final_nms_radius` pass.
+    fn finalize(&self, keypoints: Vec<Keypoint>) -> Vec<Keypoint> {
+        let keypoints = self.extractor.apply_exclusions(keypoints);
+        match self.config.keypoint.final_nms_radius {
+            Some(radius) => self.extractor.apply_nms(keypoints, radius),
+            None => keypoints,
+        }
+    }
+
+    /// Runs the model, also returning the descriptor map when `export.include_descriptors` is set.
+    fn infer(&self, input_tensor: &Tensor) -> Result<(Tensor, Option<Tensor>), SuperPointError> {
+        if self.config.export.include_descriptors {
+            let (heatmap, descriptors) = self.model.infer_with_descriptors(input_tensor)?;
+            Ok((heatmap, Some(descriptors)))
+        } else {
+            Ok((self.model.infer(input_tensor)?, None))
+        }
+    }
+
+    /// Extracts keypoints from a heatmap, returning `(original-space, model-space)` keypoints.
+    /// With a descriptor map, each keypoint also gets its sampled descriptor.
+    fn postprocess(
+        &self,
+        heatmap: &Tensor,
+        descriptors: Option<&Tensor>,
+        original_size: OriginalDims,
+        timings: &mut Timings,
+    ) -> Result<(Vec<Keypoint>, Vec<Keypoint>), SuperPointError> {
+        let start = Instant::now();
+        let mut keypoints_model_space = self.extractor.extract_keypoints(heatmap)?;
+        if let Some(descriptors) = descriptors {
+            let model_dims = ModelDims::from_config(&self.config.image);
+            keypoints_model_space = self.extractor.attach_descriptors(descriptors, keypoints_model_space, model_dims)?;
+        }
+        timings.extraction = start.elapsed();
+
+        let start = Instant::now();
+        let keypoints = self.extractor.scale_keypoints_to_original(
+            keypoints_model_space.clone(),
+            original_size,
+            ModelDims::from_config(&self.config.image),
+            self.preprocessor.letterbox(original_size),
+        );
+        timings.scaling = start.elapsed();
+
+        Ok((keypoints, keypoints_model_space))
+    }
+}
+
+/// Gaussian sigma that removes detail finer than a pyramid level can represent,
+/// `0.5 * sqrt(1 / factor² - 1)` for the stronger of the two downscale factors, or `None`
+/// when the level is not smaller than the image.
+fn anti_alias_sigma(original: OriginalDims, level: ModelDims) -> Option<f32> {
+    let factor = (level.width as f32 / original.width as f32).min(level.height as f32 / original.height as f32);
+    (factor > 0.0 && factor < 1.0).then(|| 0.5 * (1.0 / (factor * factor) - 1.0).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CameraConfig, TilingConfig};
+
+    /// A detector running the bundled `superpoint_v2.pt` on CPU.
+    fn cpu_detector(configure: impl FnOnce(&mut Config)) -> Detector {
+        let mut config = Config::default();
+        config.model.use_cuda = false;
+        configure(&mut config);
+        Detector::new(&config).expect("superpoint_v2.pt should load on CPU")
+    }
+
+    #[test]
+    fn detect_and_annotate_draws_over_input() {
+        let detector = cpu_detector(|_| {});
+        let image = image::open("input.png").unwrap();
+
+        let (keypoints, annotated) = detector.detect_and_annotate(&image).unwrap();
+
+        assert_eq!(annotated.dimensions(), image.dimensions());
+        assert!(!keypoints.is_empty());
+
+        let original = image.to_rgb8();
+        let changed = keypoints.iter().any(|kp| {
+            let (x, y) = (kp.x.round() as u32, kp.y.round() as u32);
+            x < original.width() && y < original.height() && annotated.get_pixel(x, y) != original.get_pixel(x, y)
+        });
+        assert!(changed, "no keypoint pixel differs from the input");
+    }
+
+    #[test]
+    fn detect_full_fields_are_consistent() {
+        let detector = cpu_detector(|_| {});
+        let image = image::open("input.png").unwrap();
+
+        let output = detector.detect_full(&image).unwrap();
+
+        assert!(!output.keypoints.is_empty());
+        assert_eq!(output.keypoints.len(), output.keypoints_model_space.len());
+        assert_eq!(output.heatmap.size(), vec![240, 320]);
+        assert_eq!(output.device, Device::Cpu);
+        assert!(output.timings.inference > Duration::ZERO);
+    }
+
+    #[test]
+    fn detect_from_gray_finds_checkerboard_corners() {
+        let detector = cpu_detector(|_| {});
+        let (width, height) = (320u32, 240u32);
+        let buffer: Vec<u8> = (0..width * height)
+            .map(|i| if ((i % width) / 32 + (i / width) / 32) % 2 == 0 { 0 } else { 255 })
+            .collect();
+
+        let keypoints = detector.detect_from_gray(&buffer, width, height).unwrap();
+        assert!(!keypoints.is_empty());
+
+        assert!(detector.detect_from_gray(&buffer[1..], width, height).is_err());
+    }
+
+    #[test]
+    fn pre_blur_suppresses_aliased_checkerboard_corners() {
+        // 1.3 px squares are far finer than the half-resolution level can represent; without
+        // anti-aliasing, nearest-neighbour downscaling folds them into a coarse false pattern.
+        let (width, height) = (320u32, 240u32);
+        let checkerboard = image::GrayImage::from_fn(width, height, |x, y| {
+            let (cx, cy) = ((x as f32 / 1.3) as u32, (y as f32 / 1.3) as u32);
+            image::Luma([if (cx + cy) % 2 == 0 { 0 } else { 255 }])
+        });
+        let image = DynamicImage::ImageLuma8(checkerboard);
+
+        let count = |pre_blur: bool| {
+            let detector = cpu_detector(|config| {
+                config.image.interpolation = crate::config::Interpolation::Nearest;
+                config.keypoint.max_keypoints = None;
+                config.detection.scales = vec![0.5];
+                config.detection.pre_blur = pre_blur;
+            });
+            detector.detect_pyramid(&image).unwrap().len()
+        };
+
+        let (aliased, blurred) = (count(false), count(true));
+        assert!(blurred < aliased, "pre-blur kept {} of {} spurious keypoints", blurred, aliased);
+    }
+
+    #[test]
+    fn anti_alias_sigma_only_applies_when_shrinking() {
+        let original = OriginalDims { width: 640, height: 480 };
+
+        let half = anti_alias_sigma(original, ModelDims { width: 320, height: 240 }).unwrap();
+        assert!((half - 0.5 * 3.0f32.sqrt()).abs() < 1e-6);
+        assert_eq!(anti_alias_sigma(original, ModelDims { width: 640, height: 480 }), None);
+        assert_eq!(anti_alias_sigma(original, ModelDims { width: 1280, height: 960 }), None);
+    }
+
+    #[test]
+    fn detect_crops_returns_one_list_per_crop() {
+        let detector = cpu_detector(|_| {});
+        let image = image::open("input.png").unwrap();
+        let crops: Vec<DynamicImage> = (0..3).map(|i| image.crop_imm(i * 40, 0, 320, 240)).collect();
+
+        let per_crop = detector.detect_crops(&crops).unwrap();
+
+        assert_eq!(per_crop.len(), 3);
+        for keypoints in &per_crop {
+            assert!(keypoints.iter().all(|kp| kp.x < 320.0 && kp.y < 240.0));
+        }
+    }
+
+    #[test]
+    fn report_keypoint_count_matches_returned_keypoints() {
+        let detector = cpu_detector(|_| {});
+        let image = image::open("input.png").unwrap();
+
+        let (keypoints, report) = detector.detect_report(&image).unwrap();
+
+        assert_eq!(report.keypoint_count, keypoints.len());
+        assert_eq!((report.input_width, report.input_height), image.dimensions());
+        assert_eq!(report.score_stats.is_some(), !keypoints.is_empty());
+    }
+
+    #[test]
+    fn pyramid_timings_cover_every_level() {
+        let detector = cpu_detector(|config| config.detection.scales = vec![1.0, 0.5]);
+        let image = image::open("input.png").unwrap();
+
+        let (keypoints, timings) = detector.detect_timed(&image).unwrap();
+        let (_, report) = detector.detect_report(&image).unwrap();
+
+        assert!(!keypoints.is_empty());
+        assert!(keypoints.iter().all(|kp| kp.scale.is_some()));
+        assert!(timings.inference > Duration::ZERO && timings.scaling > Duration::ZERO);
+        assert_eq!(report.keypoint_count, keypoints.len());
+    }
+
+    /// Smallest distance between any two keypoints.
+    fn closest_pair(keypoints: &[Keypoint]) -> f32 {
+        keypoints
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| keypoints[i + 1..].iter().map(move |b| a.distance_to(b)))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    #[test]
+    fn final_nms_removes_tile_seam_duplicates_after_merging() {
+        // A tiny merge radius lets both tiles' copies of a corner in an overlap band survive the
+        // merge; only a final pass over the merged set can remove those seam duplicates
+        let tiled = |final_nms_radius| {
+            cpu_detector(|config| {
+                config.keypoint.nms_radius = Some(1.0);
+                config.keypoint.final_nms_radius = final_nms_radius;
+                config.tiling = Some(TilingConfig {
+                    tile_width: 320,
+                    tile_height: 395,
+                    overlap: 48,
+                });
+            })
+        };
+        let detector = tiled(None);
+        let image = image::open("input.png").unwrap();
+        let origins: Vec<f32> = detector
+            .preprocessor
+            .split_into_tiles(&image, 320, 395, 48)
+            .iter()
+            .map(|&((x, _), _)| x as f32)
+            .collect();
+        let in_overlap = |keypoints: Vec<Keypoint>| -> Vec<Keypoint> {
+            keypoints
+                .into_iter()
+                .filter(|kp| origins.windows(2).any(|pair| kp.x >= pair[1] && kp.x < pair[0] + 320.0))
+                .collect()
+        };
+
+        let merged = in_overlap(detector.detect_tiled(&image).unwrap());
+        let final_nms = in_overlap(tiled(Some(8.0)).detect_tiled(&image).unwrap());
+
+        assert!(closest_pair(&merged) < 8.0, "expected seam duplicates before the final pass");
+        assert!(closest_pair(&final_nms) >= 8.0);
+    }
+
+    #[test]
+    fn undistort_is_identity_without_camera_and_moves_points_with_one() {
+        let keypoints = [Keypoint::new(300.0, 220.0, 0.9), Keypoint::new(160.0, 120.0, 0.8)];
+
+        let plain = cpu_detector(|_| {});
+        assert_eq!(plain.undistort(&keypoints), keypoints.to_vec());
+
+        let calibrated = cpu_detector(|config| {
+            config.camera = Some(CameraConfig {
+                fx: 500.0,
+                fy: 500.0,
+                cx: 160.0,
+                cy: 120.0,
+                distortion: [-0.3, 0.1, 0.0, 0.0, 0.0],
+            })
+        });
+        let undistorted = calibrated.undistort(&keypoints);
+
+        // Barrel distortion pulls off-centre points inwards, so undistorting pushes them out
+        assert!(undistorted[0].x > keypoints[0].x && undistorted[0].y > keypoints[0].y);
+        assert_eq!((undistorted[1].x, undistorted[1].y), (160.0, 120.0));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::config::ImageConfig;
+use crate::keypoint::Keypoint;
+use crate::postprocessing::compute_scale_factors;
+
+/// Size of the model input (and its heatmap) in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelDims {
+    pub width: i64,
+    pub height: i64,
+}
+
+impl ModelDims {
+    pub fn new(width: i64, height: i64) -> Self {
+        Self { width, height }
+    }
+    
+    /// The configured model input size.
+    pub fn from_config(config: &ImageConfig) -> Self {
+        Self::new(config.width, config.height)
+    }
+}
+
+/// Size of the original image in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OriginalDims {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl OriginalDims {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+}
+
+/// From `GenericImageView::dimensions()`, which is `(width, height)`.
+impl From<(u32, u32)> for OriginalDims {
+    fn from((width, height): (u32, u32)) -> Self {
+        Self::new(width, height)
+    }
+}
+
+// Both conversions use the same corner-aligned scaling as
+// `KeypointExtractor::scale_keypoints_to_original` (`x_image = x_model * scale_x`),
+// so they are exact inverses of each other. Neither rounds: the results are
+// continuous coordinates, and callers wanting the nearest pixel or heatmap cell
+// should `.round()` them and clamp to the target size.
+
+/// Maps an original-image pixel to model (heatmap) coordinates.
+pub fn image_to_model(x: f32, y: f32, original_size: OriginalDims, model_size: ModelDims) -> (f32, f32) {
+    let (scale_x, scale_y) = compute_scale_factors(original_size, model_size);
+    (x / scale_x, y / scale_y)
+}
+
+/// Maps model (heatmap) coordinates to an original-image pixel.
+pub fn model_to_image(x: f32, y: f32, original_size: OriginalDims, model_size: ModelDims) -> (f32, f32) {
+    let (scale_x, scale_y) = compute_scale_factors(original_size, model_size);
+    (x * scale_x, y * scale_y)
+}
+
+const UNDISTORT_ITERATIONS: usize = 10;
+
+/// Removes lens distortion from original-image keypoints.
+///
+/// `intrinsics` is `[fx, fy, cx, cy]` and `distortion` is OpenCV's `[k1, k2, p1, p2, k3]`
+/// radial/tangential model. The distortion is inverted by fixed-point iteration, as in
+/// `cv::undistortPoints`; all coefficients zero returns the keypoints unchanged.
+pub fn undistort_keypoints(keypoints: &[Keypoint], intrinsics: [f32; 4], distortion: [f32; 5]) -> Vec<Keypoint> {
+    let [fx, fy, cx, cy] = intrinsics;
+    let [k1, k2, p1, p2, k3] = distortion;
+
+    keypoints
+        .iter()
+        .map(|kp| {
+            let x0 = (kp.x - cx) / fx;
+            let y0 = (kp.y - cy) / fy;
+            let (mut x, mut y) = (x0, y0);
+
+            for _ in 0..UNDISTORT_ITERATIONS {
+                let r2 = x * x + y * y;
+                let radial = 1.0 + k1 * r2 + k2 * r2 * r2 + k3 * r2 * r2 * r2;
+                let dx = 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x);
+                let dy = p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y;
+                x = (x0 - dx) / radial;
+                y = (y0 - dy) / radial;
+            }
+
+            Keypoint {
+                x: x * fx + cx,
+                y: y * fy + cy,
+                ..kp.clone()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_and_model_coordinates_roundtrip() {
+        let original = OriginalDims::new(1280, 720);
+        let model = ModelDims::new(320, 240);
+
+        for (x, y) in [(0.0, 0.0), (517.0, 303.0), (1279.0, 719.0)] {
+            let (mx, my) = image_to_model(x, y, original, model);
+            let (ix, iy) = model_to_image(mx, my, original, model);
+
+            assert!((ix - x).abs() < 1e-3 && (iy - y).abs() < 1e-3, "({}, {}) came back as ({}, {})", x, y, ix, iy);
+        }
+    }
+
+    #[test]
+    fn rounded_model_cell_maps_back_within_one_scale_step() {
+        let original = OriginalDims::new(1280, 720);
+        let model = ModelDims::new(320, 240);
+        let (scale_x, scale_y) = compute_scale_factors(original, model);
+
+        let (mx, my) = image_to_model(517.0, 303.0, original, model);
+        let (ix, iy) = model_to_image(mx.round(), my.round(), original, model);
+
+        assert!((ix - 517.0).abs() <= scale_x / 2.0 && (iy - 303.0).abs() <= scale_y / 2.0);
+    }
+
+    #[test]
+    fn undistortion_is_identity_without_coefficients_and_pulls_inward_with_barrel() {
+        let intrinsics = [100.0, 100.0, 160.0, 120.0];
+        let keypoints = [Keypoint::new(211.25, 120.0, 0.9), Keypoint::new(160.0, 120.0, 0.8)];
+
+        let unchanged = undistort_keypoints(&keypoints, intrinsics, [0.0; 5]);
+        for (kp, original) in unchanged.iter().zip(&keypoints) {
+            assert!((kp.x - original.x).abs() < 1e-4 && (kp.y - original.y).abs() < 1e-4);
+        }
+
+        // Normalized x = 0.5 is distorted to 0.5 * (1 + 0.1 * 0.25) = 0.5125, i.e. pixel 211.25
+        let undistorted = undistort_keypoints(&keypoints, intrinsics, [0.1, 0.0, 0.0, 0.0, 0.0]);
+        assert!((undistorted[0].x - 210.0).abs() < 1e-3, "{}", undistorted[0].x);
+        assert_eq!(undistorted[0].y, 120.0);
+        assert_eq!((undistorted[1].x, undistorted[1].y), (160.0, 120.0));
+        assert_eq!(undistorted[0].score, 0.9);
+    }
+}
@@ -1,4 +1,6 @@
+use crate::config::{DescriptorEncoding, ExportConfig, SortOrder};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Keypoint {
@@ -7,6 +9,8 @@ pub struct Keypoint {
     pub score: f32,
     pub scale: Option<f32>,
     pub angle: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub descriptor: Option<Vec<f32>>,
 }
 
 impl Keypoint {
@@ -17,6 +21,7 @@ impl Keypoint {
             score,
             scale: None,
             angle: None,
+            descriptor: None,
         }
     }
     
@@ -27,12 +32,362 @@ impl Keypoint {
             score,
             scale: Some(scale),
             angle: Some(angle),
+            descriptor: None,
         }
     }
     
+    /// Ranking order: descending score with NaN scores last, ties broken by `(y, x)` so equal
+    /// scores always come out in raster order.
+    pub fn cmp_by_score(&self, other: &Keypoint) -> Ordering {
+        match (self.score.is_nan(), other.score.is_nan()) {
+            (false, true) => Ordering::Less,
+            (true, false) => Ordering::Greater,
+            _ => other.score.total_cmp(&self.score),
+        }
+        .then_with(|| self.y.total_cmp(&other.y))
+        .then_with(|| self.x.total_cmp(&other.x))
+    }
+    
     pub fn distance_to(&self, other: &Keypoint) -> f32 {
         ((self.x - other.x).powi(2) + (self.y - other.y).powi(2)).sqrt()
     }
+    
+    pub fn rounded(&self, precision: usize) -> Keypoint {
+        let factor = 10f32.powi(precision as i32);
+        let round = |value: f32| (value * factor).round() / factor;
+        
+        Self {
+            x: round(self.x),
+            y: round(self.y),
+            score: round(self.score),
+            ..self.clone()
+        }
+    }
+}
+
+/// Elliptical affine-covariant region around a keypoint.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AffineRegion {
+    pub x: f32,
+    pub y: f32,
+    /// Semi-axis lengths in pixels
+    pub major_axis: f32,
+    pub minor_axis: f32,
+    /// Orientation of the major axis in radians
+    pub angle: f32,
+}
+
+/// Applies the export settings to a keypoint list right before it is serialized.
+pub fn prepare_for_export(keypoints: &[Keypoint], config: &ExportConfig) -> Vec<Keypoint> {
+    let mut prepared = match config.coord_precision {
+        Some(precision) => keypoints.iter().map(|kp| kp.rounded(precision)).collect(),
+        None => keypoints.to_vec(),
+    };
+    
+    match config.sort_order {
+        SortOrder::ScoreDesc => prepared.sort_by(Keypoint::cmp_by_score),
+        SortOrder::RasterOrder => prepared.sort_by(|a, b| {
+            a.y.round()
+                .total_cmp(&b.y.round())
+                .then_with(|| a.x.round().total_cmp(&b.x.round()))
+        }),
+        SortOrder::None => {}
+    }
+    
+    prepared
+}
+
+/// JSON envelope written by `export_json`. `K` is `Base64Keypoint` on disk when descriptors
+/// are base64-encoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeypointExport<K = Keypoint> {
+    pub image_width: u32,
+    pub image_height: u32,
+    /// Detection threshold the keypoints were extracted with
+    pub threshold: f64,
+    pub keypoints: Vec<K>,
+}
+
+#[cfg(feature = "base64")]
+impl<K> KeypointExport<K> {
+    fn with_keypoints<T>(&self, keypoints: Vec<T>) -> KeypointExport<T> {
+        KeypointExport {
+            image_width: self.image_width,
+            image_height: self.image_height,
+            threshold: self.threshold,
+            keypoints,
+        }
+    }
+}
+
+/// Writes keypoints as a JSON object recording the image size and detection threshold.
+/// Descriptors are written as number arrays or, with `DescriptorEncoding::Base64`, as base64
+/// strings (see `Base64Keypoint`).
+pub fn export_json(
+    keypoints: &[Keypoint],
+    image_size: (u32, u32),
+    threshold: f64,
+    encoding: DescriptorEncoding,
+    path: &str,
+) -> Result<(), crate::error::SuperPointError> {
+    let export = KeypointExport {
+        image_width: image_size.0,
+        image_height: image_size.1,
+        threshold,
+        keypoints: keypoints.to_vec(),
+    };
+    
+    let json = match encoding {
+        DescriptorEncoding::Array => serde_json::to_string_pretty(&export),
+        #[cfg(feature = "base64")]
+        DescriptorEncoding::Base64 => {
+            serde_json::to_string_pretty(&export.with_keypoints(keypoints.iter().map(Base64Keypoint::from).collect()))
+        }
+        #[cfg(not(feature = "base64"))]
+        DescriptorEncoding::Base64 => return Err(base64_unavailable()),
+    }
+    .map_err(std::io::Error::other)?;
+    std::fs::write(path, json)?;
+    Ok(())
+}
+
+/// Reads back a file written by `export_json` with the same `encoding`.
+pub fn import_json(path: &str, encoding: DescriptorEncoding) -> Result<KeypointExport, crate::error::SuperPointError> {
+    let json = std::fs::read_to_string(path)?;
+    
+    match encoding {
+        DescriptorEncoding::Array => Ok(serde_json::from_str(&json).map_err(std::io::Error::other)?),
+        #[cfg(feature = "base64")]
+        DescriptorEncoding::Base64 => {
+            let encoded: KeypointExport<Base64Keypoint> = serde_json::from_str(&json).map_err(std::io::Error::other)?;
+            let keypoints = encoded
+                .keypoints
+                .iter()
+                .cloned()
+                .map(Keypoint::try_from)
+                .collect::<Result<_, _>>()?;
+            Ok(encoded.with_keypoints(keypoints))
+        }
+        #[cfg(not(feature = "base64"))]
+        DescriptorEncoding::Base64 => Err(base64_unavailable()),
+    }
+}
+
+#[cfg(not(feature = "base64"))]
+fn base64_unavailable() -> crate::error::SuperPointError {
+    crate::error::SuperPointError::Config(
+        "descriptor_encoding = \"Base64\" requires building with the `base64` feature".to_string(),
+    )
+}
+
+/// Writes `x,y,score,scale,angle` rows in the given order; unset scale/angle are left empty.
+/// Run the keypoints through `prepare_for_export` first for the configured sort order
+/// (highest score first by default) and precision.
+pub fn export_csv(keypoints: &[Keypoint], path: &str) -> Result<(), crate::error::SuperPointError> {
+    use std::io::Write;
+    
+    let optional = |value: Option<f32>| value.map(|v| v.to_string()).unwrap_or_default();
+    
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(writer, "x,y,score,scale,angle")?;
+    for kp in keypoints {
+        writeln!(writer, "{},{},{},{},{}", kp.x, kp.y, kp.score, optional(kp.scale), optional(kp.angle))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// `size` OpenCV records for a keypoint without a scale: one 8x8 SuperPoint cell.
+const OPENCV_DEFAULT_SIZE: f32 = 8.0;
+
+/// Writes keypoints as `cv::FileStorage` YAML readable with `fs["keypoints"] >> keypoints`.
+/// OpenCV stores `std::vector<cv::KeyPoint>` as one flow sequence of
+/// `x, y, size, angle, response, octave, class_id` per keypoint, mapped as:
+///
+/// - `pt` ← `(x, y)`
+/// - `response` ← `score`
+/// - `size` ← `8 * scale`, the cell size at that pyramid level; 8 when `scale` is unset
+/// - `octave` ← `round(log2(scale))`, so pyramid level 2x is octave 1; 0 when `scale` is unset
+/// - `angle` ← `angle` converted to degrees in `[0, 360)`; -1 (OpenCV's "not applicable") when unset
+/// - `class_id` ← always -1
+///
+/// Nothing is re-sorted or rounded here; `prepare_for_export` applies the `[export]` settings.
+pub fn export_opencv_yaml(keypoints: &[Keypoint], path: &str) -> Result<(), crate::error::SuperPointError> {
+    use std::io::Write;
+    
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(writer, "%YAML:1.0")?;
+    writeln!(writer, "---")?;
+    write!(writer, "keypoints: [")?;
+    for (i, kp) in keypoints.iter().enumerate() {
+        let size = kp.scale.map_or(OPENCV_DEFAULT_SIZE, |scale| OPENCV_DEFAULT_SIZE * scale);
+        let octave = kp.scale.map_or(0, |scale| scale.log2().round() as i32);
+        let angle = kp.angle.map_or(-1.0, |angle| angle.to_degrees().rem_euclid(360.0));
+        
+        let separator = if i == 0 { "" } else { "," };
+        write!(
+            writer,
+            "{}\n    {:?}, {:?}, {:?}, {:?}, {:?}, {}, -1",
+            separator, kp.x, kp.y, size, angle, kp.score, octave
+        )?;
+    }
+    writeln!(writer, " ]")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Descriptor length `colmap feature_importer` requires; SuperPoint's own are not SIFT-compatible.
+const COLMAP_DESCRIPTOR_DIM: usize = 128;
+
+/// Writes one image's keypoints in the text format read by `colmap feature_importer`
+/// (`<image name>.txt` next to the image): a `N 128` header, then `x y scale orientation`
+/// followed by 128 descriptor values per row. Unset scale defaults to 1 and unset angle to 0
+/// radians. Descriptors are written as zeros, since COLMAP only accepts 128-d uint8 SIFT-style
+/// descriptors; match with SuperPoint descriptors and import the result with
+/// `matching::export_colmap` instead of letting COLMAP match.
+pub fn export_colmap_features(keypoints: &[Keypoint], path: &str) -> Result<(), crate::error::SuperPointError> {
+    use std::io::Write;
+    
+    let descriptor = vec!["0"; COLMAP_DESCRIPTOR_DIM].join(" ");
+    
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(writer, "{} {}", keypoints.len(), COLMAP_DESCRIPTOR_DIM)?;
+    for kp in keypoints {
+        writeln!(
+            writer,
+            "{} {} {} {} {}",
+            kp.x,
+            kp.y,
+            kp.scale.unwrap_or(1.0),
+            kp.angle.unwrap_or(0.0),
+            descriptor
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Keypoint whose descriptor is stored as base64 of its little-endian f32 bytes.
+#[cfg(feature = "base64")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Base64Keypoint {
+    pub x: f32,
+    pub y: f32,
+    pub score: f32,
+    pub scale: Option<f32>,
+    pub angle: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub descriptor: Option<String>,
+}
+
+#[cfg(feature = "base64")]
+impl From<&Keypoint> for Base64Keypoint {
+    fn from(kp: &Keypoint) -> Self {
+        Self {
+            x: kp.x,
+            y: kp.y,
+            score: kp.score,
+            scale: kp.scale,
+            angle: kp.angle,
+            descriptor: kp.descriptor.as_deref().map(encode_descriptor_base64),
+        }
+    }
+}
+
+#[cfg(feature = "base64")]
+impl TryFrom<Base64Keypoint> for Keypoint {
+    type Error = crate::error::SuperPointError;
+
+    fn try_from(kp: Base64Keypoint) -> Result<Self, Self::Error> {
+        Ok(Self {
+            x: kp.x,
+            y: kp.y,
+            score: kp.score,
+            scale: kp.scale,
+            angle: kp.angle,
+            descriptor: kp.descriptor.as_deref().map(decode_descriptor_base64).transpose()?,
+        })
+    }
+}
+
+#[cfg(feature = "base64")]
+pub fn encode_descriptor_base64(descriptor: &[f32]) -> String {
+    use base64::Engine;
+    
+    let bytes: Vec<u8> = descriptor.iter().flat_map(|v| v.to_le_bytes()).collect();
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+#[cfg(feature = "base64")]
+pub fn decode_descriptor_base64(encoded: &str) -> Result<Vec<f32>, crate::error::SuperPointError> {
+    use base64::Engine;
+    use crate::error::SuperPointError;
+    
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| SuperPointError::KeypointExtraction(format!("Invalid base64 descriptor: {}", e)))?;
+    
+    if bytes.len() % 4 != 0 {
+        return Err(SuperPointError::KeypointExtraction(format!(
+            "Descriptor byte length {} is not a multiple of 4",
+            bytes.len()
+        )));
+    }
+    
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect())
+}
+
+/// Writes one image's features into an HLOC-style `features.h5`: a group named `image_name`
+/// holding `keypoints` `[N, 2]`, `scores` `[N]` and `descriptors` `[D, N]` (HLOC stores them
+/// transposed). `descriptors` is the `[N, D]` output of `sample_descriptors`. An existing group
+/// of the same name is replaced; other images in the file are kept.
+#[cfg(feature = "hdf5")]
+pub fn write_hloc(
+    path: impl AsRef<std::path::Path>,
+    image_name: &str,
+    keypoints: &[Keypoint],
+    descriptors: &tch::Tensor,
+) -> Result<(), crate::error::SuperPointError> {
+    use crate::error::SuperPointError;
+    use tch::{Device, Kind};
+    
+    let count = keypoints.len();
+    if descriptors.dim() != 2 || descriptors.size()[0] != count as i64 {
+        return Err(SuperPointError::KeypointExtraction(format!(
+            "Expected descriptors [{}, D], got {:?}",
+            count,
+            descriptors.size()
+        )));
+    }
+    let dim = descriptors.size()[1] as usize;
+    
+    let coords: Vec<f32> = keypoints.iter().flat_map(|kp| [kp.x, kp.y]).collect();
+    let scores: Vec<f32> = keypoints.iter().map(|kp| kp.score).collect();
+    let descriptors: Vec<f32> = Vec::try_from(
+        descriptors
+            .to_kind(Kind::Float)
+            .to_device(Device::Cpu)
+            .transpose(0, 1)
+            .contiguous()
+            .view((-1,)),
+    )?;
+    
+    let write = || -> hdf5::Result<()> {
+        let file = hdf5::File::append(path)?;
+        if file.link_exists(image_name) {
+            file.unlink(image_name)?;
+        }
+        let group = file.create_group(image_name)?;
+        group.new_dataset::<f32>().shape((count, 2)).create("keypoints")?.write_raw(&coords)?;
+        group.new_dataset::<f32>().shape(count).create("scores")?.write_raw(&scores)?;
+        group.new_dataset::<f32>().shape((dim, count)).create("descriptors")?.write_raw(&descriptors)?;
+        Ok(())
+    };
+    
+    write().map_err(|e| SuperPointError::Io(std::io::Error::other(format!("Failed to write HLOC features: {}", e))))
 }
 
 #[derive(Debug, Clone)]
@@ -51,4 +406,186 @@ impl KeypointMatch {
             distance,
         }
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coord_precision_limits_serialized_decimals() {
+        let config = ExportConfig {
+            coord_precision: Some(2),
+            ..ExportConfig::default()
+        };
+        let keypoints = vec![
+            Keypoint::new(12.345_67, 8.910_11, 0.876_543),
+            Keypoint::new(100.005, 3.987_65, 0.123_456),
+        ];
+        
+        for kp in prepare_for_export(&keypoints, &config) {
+            for value in [kp.x, kp.y, kp.score] {
+                let serialized = serde_json::to_string(&value).unwrap();
+                let decimals = serialized.split_once('.').map_or(0, |(_, fraction)| fraction.len());
+                assert!(decimals <= 2, "{} has more than two decimals", serialized);
+            }
+        }
+    }
+    
+    #[test]
+    fn raster_order_ignores_scores() {
+        let config = ExportConfig {
+            sort_order: SortOrder::RasterOrder,
+            ..ExportConfig::default()
+        };
+        let bottom_right = Keypoint::new(300.0, 200.0, 0.9);
+        let top_left = Keypoint::new(10.0, 5.0, 0.1);
+        let top_right = Keypoint::new(300.0, 5.2, 0.5);
+        
+        let sorted = prepare_for_export(&[bottom_right.clone(), top_right.clone(), top_left.clone()], &config);
+        
+        // 5.2 rounds to row 5, so the top row is ordered by x
+        assert_eq!(sorted, vec![top_left, top_right, bottom_right]);
+    }
+    
+    #[test]
+    fn csv_keeps_given_order_and_leaves_unset_fields_empty() {
+        let path = std::env::temp_dir()
+            .join(format!("superpoint-{}-keypoints.csv", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let oriented = Keypoint {
+            scale: Some(2.0),
+            angle: Some(0.5),
+            ..Keypoint::new(3.0, 4.0, 0.9)
+        };
+        
+        export_csv(&[Keypoint::new(1.0, 2.0, 0.25), oriented], &path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        
+        assert_eq!(csv, "x,y,score,scale,angle\n1,2,0.25,,\n3,4,0.9,2,0.5\n");
+    }
+    
+    #[test]
+    fn score_order_breaks_ties_by_row_then_column_and_sinks_nan() {
+        let mut keypoints = [
+            Keypoint::new(0.0, 0.0, f32::NAN),
+            Keypoint::new(7.0, 3.0, 0.5),
+            Keypoint::new(2.0, 3.0, 0.5),
+            Keypoint::new(9.0, 1.0, 0.5),
+            Keypoint::new(4.0, 8.0, 0.9),
+        ];
+        
+        keypoints.sort_by(Keypoint::cmp_by_score);
+        
+        let positions: Vec<_> = keypoints.iter().map(|kp| (kp.x, kp.y)).collect();
+        assert_eq!(positions, vec![(4.0, 8.0), (9.0, 1.0), (2.0, 3.0), (7.0, 3.0), (0.0, 0.0)]);
+    }
+    
+    #[test]
+    fn opencv_yaml_writes_prepared_keypoints_in_order() {
+        let path = std::env::temp_dir()
+            .join(format!("superpoint-{}-keypoints.yml", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let config = ExportConfig {
+            coord_precision: Some(1),
+            ..ExportConfig::default()
+        };
+        let oriented = Keypoint {
+            scale: Some(2.0),
+            angle: Some(0.0),
+            ..Keypoint::new(3.0, 4.0, 0.9)
+        };
+        
+        let prepared = prepare_for_export(&[Keypoint::new(1.26, 2.0, 0.25), oriented], &config);
+        export_opencv_yaml(&prepared, &path).unwrap();
+        let yaml = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        
+        assert_eq!(
+            yaml,
+            "%YAML:1.0\n---\nkeypoints: [\n    3.0, 4.0, 16.0, 0.0, 0.9, 1, -1,\n    1.3, 2.0, 8.0, -1.0, 0.3, 0, -1 ]\n"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "base64"))]
+mod base64_tests {
+    use super::*;
+    
+    fn described(x: f32, y: f32, descriptor: Vec<f32>) -> Keypoint {
+        Keypoint {
+            descriptor: Some(descriptor),
+            ..Keypoint::new(x, y, 0.5)
+        }
+    }
+    
+    #[test]
+    fn descriptor_base64_roundtrip_is_lossless() {
+        let descriptor = vec![0.0, -1.5, f32::MIN_POSITIVE, 0.123_456, f32::MAX];
+        
+        let decoded = decode_descriptor_base64(&encode_descriptor_base64(&descriptor)).unwrap();
+        
+        assert_eq!(decoded, descriptor);
+    }
+    
+    #[test]
+    fn json_export_roundtrips_base64_descriptors() {
+        let path = std::env::temp_dir()
+            .join(format!("superpoint-{}-base64.json", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let keypoints = vec![
+            described(1.0, 2.0, vec![0.6, 0.8]),
+            described(3.0, 4.0, vec![-1.0, 0.0]),
+            Keypoint::new(5.0, 6.0, 0.1),
+        ];
+        
+        export_json(&keypoints, (64, 48), 0.05, DescriptorEncoding::Base64, &path).unwrap();
+        let raw = std::fs::read_to_string(&path).unwrap();
+        let imported = import_json(&path, DescriptorEncoding::Base64).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        
+        assert!(raw.contains(&format!("\"{}\"", encode_descriptor_base64(&[0.6, 0.8]))));
+        assert_eq!(imported.keypoints, keypoints);
+        assert_eq!((imported.image_width, imported.image_height), (64, 48));
+    }
+    
+    #[test]
+    fn invalid_base64_descriptor_is_rejected() {
+        assert!(decode_descriptor_base64("not base64!").is_err());
+        assert!(decode_descriptor_base64("AAA=").is_err());
+    }
+}
+
+#[cfg(all(test, feature = "hdf5"))]
+mod hdf5_tests {
+    use super::*;
+    
+    #[test]
+    fn hloc_features_read_back_with_expected_shapes() {
+        let path = std::env::temp_dir().join(format!("superpoint-{}-features.h5", std::process::id()));
+        let keypoints = vec![
+            Keypoint::new(1.0, 2.0, 0.9),
+            Keypoint::new(3.0, 4.0, 0.5),
+            Keypoint::new(5.0, 6.0, 0.1),
+        ];
+        let descriptors = tch::Tensor::rand([3, 256], (tch::Kind::Float, tch::Device::Cpu));
+        
+        write_hloc(&path, "a.png", &keypoints, &descriptors).unwrap();
+        let file = hdf5::File::open(&path).unwrap();
+        let group = file.group("a.png").unwrap();
+        let keypoint_shape = group.dataset("keypoints").unwrap().shape();
+        let score_shape = group.dataset("scores").unwrap().shape();
+        let descriptor_shape = group.dataset("descriptors").unwrap().shape();
+        let coords: Vec<f32> = group.dataset("keypoints").unwrap().read_raw().unwrap();
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+        
+        assert_eq!(keypoint_shape, vec![3, 2]);
+        assert_eq!(score_shape, vec![3]);
+        assert_eq!(descriptor_shape, vec![256, 3]);
+        assert_eq!(coords, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+}
@@ -1,12 +1,21 @@
+pub mod batch;
 pub mod config;
+pub mod detector;
 pub mod error;
+pub mod geometry;
 pub mod model;
 pub mod preprocessing;
 pub mod postprocessing;
 pub mod visualization;
 pub mod keypoint;
+pub mod mapping;
+pub mod matching;
+pub mod report;
+#[cfg(feature = "video")]
+pub mod video;
 
 pub use error::SuperPointError;
 pub use config::Config;
+pub use detector::Detector;
 pub use keypoint::Keypoint;
 pub use model::SuperPointModel; 
\ No newline at end of file
@@ -1,13 +1,165 @@
-use clap::{Arg, ArgAction, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use image::GenericImageView;
-use log::info;
+use log::{info, warn};
 use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 use superpoint_rs::*;
+use superpoint_rs::detector::{DetectionOutput, Detector};
+use superpoint_rs::report::Report;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
-    let matches = Command::new("SuperPoint Keypoint Detector")
+    let matches = cli().get_matches();
+
+    // Load or create configuration
+    let mut config = if let Some(config_path) = matches.get_one::<String>("config") {
+        println!("Loading configuration from: {}", config_path);
+        Config::from_file(config_path)?
+    } else if Path::new("config.toml").exists() {
+        println!("Auto-detected config.toml, loading configuration...");
+        Config::from_file("config.toml")?
+    } else {
+        println!("Using default configuration");
+        Config::default()
+    };
+
+    // Print current configuration for debugging
+    println!("Configuration:");
+    println!("  Threshold: {}", config.keypoint.threshold);
+    println!("  Max keypoints: {:?}", config.keypoint.max_keypoints);
+    println!("  NMS radius: {:?}", config.keypoint.nms_radius);
+    println!("  Circle radius: {}", config.visualization.circle_radius);
+
+    // Override config with command line arguments
+    let file_config = config.clone();
+    apply_overrides(&mut config, &matches);
+
+    config.validate()?;
+
+    let overrides = file_config.diff(&config);
+    if !overrides.is_empty() {
+        println!("Command-line overrides:");
+        for (field, from, to) in &overrides {
+            println!("  {}: {} -> {}", field, from, to);
+        }
+    }
+
+    // Save configuration if requested
+    if let Some(save_path) = matches.get_one::<String>("save-config") {
+        config.to_file(save_path)?;
+        println!("Configuration saved to {}", save_path);
+    }
+
+    let input_paths: Vec<&String> = matches.get_many::<String>("input").map(Iterator::collect).unwrap_or_default();
+    let input_dir = matches.get_one::<String>("input-dir");
+    let output_path = match input_dir {
+        Some(_) => matches.get_one::<String>("output-dir").unwrap(),
+        None => matches.get_one::<String>("output").unwrap(),
+    };
+
+    if let Some(input_dir) = input_dir
+        && !Path::new(input_dir).is_dir()
+    {
+        eprintln!("Error: Input directory '{}' does not exist", input_dir);
+        std::process::exit(1);
+    }
+
+    // Validate input files exist
+    for input_path in &input_paths {
+        if !Path::new(input_path.as_str()).exists() {
+            eprintln!("Error: Input file '{}' does not exist", input_path);
+            std::process::exit(1);
+        }
+    }
+
+    // Validate model file exists
+    if !config.model.path.exists() {
+        eprintln!("Error: Model file '{:?}' does not exist", config.model.path);
+        std::process::exit(1);
+    }
+
+    // Record the exact settings used for this run next to its output
+    if matches.get_flag("save-run-config") {
+        let run_config_path = save_run_config(&config, output_path)?;
+        info!("Run configuration saved to {}", run_config_path);
+    }
+
+    info!("Starting SuperPoint keypoint detection");
+    info!("Input: {:?}", input_dir.map_or(input_paths.clone(), |dir| vec![dir]));
+    info!("Output: {}", output_path);
+    info!("Model: {:?}", config.model.path);
+
+    // Run the detection pipeline
+    let exports = ExportFormats {
+        json: matches.get_flag("export-json"),
+        csv: matches.get_flag("export-csv"),
+        opencv: matches.get_flag("export-opencv"),
+    };
+    // Directory runs that skipped failed images still succeed, but exit nonzero
+    let mut exit_code = 0;
+    let result = if let Some(input_dir) = input_dir {
+        let options = DirectoryOptions {
+            jobs: *matches.get_one::<usize>("jobs").unwrap(),
+            devices: matches.get_one::<String>("devices").cloned(),
+            warmup: matches.get_flag("warmup"),
+            exports,
+        };
+        run_directory(&config, Path::new(input_dir), Path::new(output_path), &options).and_then(|manifest| {
+            if let Some(failure) = manifest.failed.first()
+                && manifest.aborted
+            {
+                return Err(SuperPointError::ImageProcessing(format!(
+                    "Aborted after {} failed: {}",
+                    failure.input.display(),
+                    failure.error
+                )));
+            }
+            if manifest.exit_code() != 0 {
+                let manifest_path = Path::new(output_path).join(batch::MANIFEST_FILE);
+                eprintln!("❌ {} images failed, see {}", manifest.failed.len(), manifest_path.display());
+                exit_code = manifest.exit_code();
+            }
+            Ok(manifest.keypoint_total())
+        })
+    } else if input_paths.len() > 1 {
+        let cols = *matches.get_one::<usize>("montage-cols").unwrap();
+        run_montage(&config, &input_paths, output_path, cols)
+    } else {
+        let bands: Option<Vec<f64>> = matches.get_many::<f64>("bands").map(|b| b.copied().collect());
+        let options = RunOptions {
+            warmup: matches.get_flag("warmup"),
+            timings: matches.get_flag("timings"),
+            save_heatmap: matches.get_flag("save-heatmap"),
+            heatmap_keypoints: matches.get_flag("heatmap-keypoints"),
+            bands: bands.as_deref(),
+            report_path: matches.get_one::<String>("report").map(String::as_str),
+            exports,
+        };
+        run_detection(&config, input_paths[0], output_path, &options)
+    };
+
+    match result {
+        Ok(num_keypoints) => {
+            println!("✅ Successfully detected {} keypoints", num_keypoints);
+            println!("   Results saved to: {}", output_path);
+            if exit_code != 0 {
+                std::process::exit(exit_code);
+            }
+        }
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+fn cli() -> Command {
+    Command::new("SuperPoint Keypoint Detector")
         .version("0.1.0")
         .author("Brandon Friend")
         .about("Rust implementation of SuperPoint keypoint detection")
@@ -16,8 +168,54 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .short('i')
                 .long("input")
                 .value_name("FILE")
-                .help("Input image path")
-                .required(true),
+                .help("Input image path (repeat to render a montage of several images)")
+                .required_unless_present("input-dir")
+                .conflicts_with("input-dir")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("input-dir")
+                .long("input-dir")
+                .value_name("DIR")
+                .help("Detect keypoints in every image of a directory, loading the model once"),
+        )
+        .arg(
+            Arg::new("output-dir")
+                .long("output-dir")
+                .value_name("DIR")
+                .help("Where --input-dir writes the annotated images")
+                .default_value("output"),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("N")
+                .help("Images processed concurrently by --input-dir")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1"),
+        )
+        .arg(
+            Arg::new("devices")
+                .long("devices")
+                .value_name("all|0,1,...")
+                .help("Load the model on each listed CUDA device and share --input-dir images between them")
+                .requires("input-dir")
+                .conflicts_with("no-cuda"),
+        )
+        .arg(
+            Arg::new("on-collision")
+                .long("on-collision")
+                .value_name("POLICY")
+                .help("When --input-dir inputs share a stem (a.png, a.jpg): overwrite, skip or suffix")
+                .value_parser(["overwrite", "skip", "suffix"]),
+        )
+        .arg(
+            Arg::new("on-error")
+                .long("on-error")
+                .value_name("POLICY")
+                .help("When an --input-dir image fails: skip it (exit code still nonzero) or abort")
+                .value_parser(["skip", "abort"]),
         )
         .arg(
             Arg::new("output")
@@ -50,6 +248,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Keypoint detection threshold")
                 .value_parser(clap::value_parser!(f64)),
         )
+        .arg(
+            Arg::new("top-k")
+                .long("top-k")
+                .value_name("INT")
+                .help("Keep about this many of the strongest keypoints instead of thresholding")
+                .value_parser(clap::value_parser!(usize))
+                .conflicts_with("threshold"),
+        )
         .arg(
             Arg::new("max-keypoints")
                 .long("max-keypoints")
@@ -57,46 +263,116 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Maximum number of keypoints to detect")
                 .value_parser(clap::value_parser!(usize)),
         )
+        .arg(
+            Arg::new("bands")
+                .long("bands")
+                .value_name("FLOAT,...")
+                .help("Color keypoints by confidence band, e.g. 0.5,0.2,0.05")
+                .value_parser(clap::value_parser!(f64))
+                .value_delimiter(',')
+                .conflicts_with("input-dir"),
+        )
+        .arg(
+            Arg::new("final-nms")
+                .long("final-nms")
+                .value_name("RADIUS")
+                .help("Run a second NMS pass on the scaled, original-space keypoints")
+                .value_parser(clap::value_parser!(f32)),
+        )
+        .arg(
+            Arg::new("mask")
+                .long("mask")
+                .value_name("FILE")
+                .help("Grayscale region-of-interest mask; keypoints on pixels below 128 are dropped"),
+        )
         .arg(
             Arg::new("no-cuda")
                 .long("no-cuda")
-                .help("Disable CUDA acceleration")
+                .help("Force CPU inference (disables CUDA and MPS)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("warmup")
+                .long("warmup")
+                .help("Run one throwaway inference first so lazy CUDA initialization is not timed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("timings")
+                .long("timings")
+                .conflicts_with("input-dir")
+                .help("Print how long each pipeline stage took")
                 .action(ArgAction::SetTrue),
         )
         .arg(
             Arg::new("save-heatmap")
                 .long("save-heatmap")
+                .conflicts_with("input-dir")
                 .help("Save heatmap visualization")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("heatmap-keypoints")
+                .long("heatmap-keypoints")
+                .conflicts_with("input-dir")
+                .help("Mark detected keypoints on the saved heatmap")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("draw-nms-radius")
+                .long("draw-nms-radius")
+                .help("Outline each keypoint's NMS radius (from config) in the output")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("montage-cols")
+                .long("montage-cols")
+                .value_name("INT")
+                .help("Number of columns in the montage when several inputs are given")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("2"),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("FILE")
+                .help("Write a JSON run report (config, input hash, device, timings, keypoint stats)")
+                .conflicts_with("input-dir"),
+        )
+        .arg(
+            Arg::new("export-json")
+                .long("export-json")
+                .help("Also write the keypoints as JSON next to the output image (<output>.json)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("export-csv")
+                .long("export-csv")
+                .help("Also write the keypoints as CSV next to the output image (<output>.csv)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("export-opencv")
+                .long("export-opencv")
+                .help("Also write the keypoints as OpenCV FileStorage YAML (<output>.yml)")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("save-config")
                 .long("save-config")
                 .value_name("FILE")
                 .help("Save current configuration to file"),
         )
-        .get_matches();
-
-    // Load or create configuration
-    let mut config = if let Some(config_path) = matches.get_one::<String>("config") {
-        println!("Loading configuration from: {}", config_path);
-        Config::from_file(config_path)?
-    } else if Path::new("config.toml").exists() {
-        println!("Auto-detected config.toml, loading configuration...");
-        Config::from_file("config.toml")?
-    } else {
-        println!("Using default configuration");
-        Config::default()
-    };
-
-    // Print current configuration for debugging
-    println!("Configuration:");
-    println!("  Threshold: {}", config.keypoint.threshold);
-    println!("  Max keypoints: {:?}", config.keypoint.max_keypoints);
-    println!("  NMS radius: {:?}", config.keypoint.nms_radius);
-    println!("  Circle radius: {}", config.visualization.circle_radius);
+        .arg(
+            Arg::new("save-run-config")
+                .long("save-run-config")
+                .help("Write the fully-resolved configuration next to the output as <output>.config.toml")
+                .action(ArgAction::SetTrue),
+        )
+}
 
-    // Override config with command line arguments
+/// Applies the command-line settings on top of the loaded configuration.
+fn apply_overrides(config: &mut Config, matches: &ArgMatches) {
     if let Some(model_path) = matches.get_one::<String>("model") {
         config.model.path = model_path.into();
     }
@@ -105,109 +381,393 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         config.keypoint.threshold = threshold;
     }
 
+    if let Some(&k) = matches.get_one::<usize>("top-k") {
+        config.keypoint.top_k = Some(k);
+    }
+
     if let Some(&max_kpts) = matches.get_one::<usize>("max-keypoints") {
         config.keypoint.max_keypoints = Some(max_kpts);
     }
 
-    if matches.get_flag("no-cuda") {
-        config.model.use_cuda = false;
+    if let Some(&radius) = matches.get_one::<f32>("final-nms") {
+        config.keypoint.final_nms_radius = Some(radius);
     }
 
-    // Save configuration if requested
-    if let Some(save_path) = matches.get_one::<String>("save-config") {
-        config.to_file(save_path)?;
-        println!("Configuration saved to {}", save_path);
+    if let Some(mask_path) = matches.get_one::<String>("mask") {
+        config.keypoint.mask = Some(mask_path.into());
     }
 
-    let input_path = matches.get_one::<String>("input").unwrap();
-    let output_path = matches.get_one::<String>("output").unwrap();
+    if let Some(policy) = matches.get_one::<String>("on-collision") {
+        config.batch.on_collision = match policy.as_str() {
+            "overwrite" => config::CollisionPolicy::Overwrite,
+            "skip" => config::CollisionPolicy::Skip,
+            _ => config::CollisionPolicy::Suffix,
+        };
+    }
 
-    // Validate input file exists
-    if !Path::new(input_path).exists() {
-        eprintln!("Error: Input file '{}' does not exist", input_path);
-        std::process::exit(1);
+    if let Some(policy) = matches.get_one::<String>("on-error") {
+        config.batch.on_error = match policy.as_str() {
+            "abort" => config::ErrorPolicy::Abort,
+            _ => config::ErrorPolicy::Skip,
+        };
     }
 
-    // Validate model file exists
-    if !config.model.path.exists() {
-        eprintln!("Error: Model file '{:?}' does not exist", config.model.path);
-        std::process::exit(1);
+    if matches.get_flag("draw-nms-radius") {
+        config.visualization.draw_nms_radius = true;
     }
 
-    info!("Starting SuperPoint keypoint detection");
-    info!("Input: {}", input_path);
-    info!("Output: {}", output_path);
-    info!("Model: {:?}", config.model.path);
+    if matches.get_flag("no-cuda") {
+        config.model.use_cuda = false;
+        config.model.device_preference = config::DevicePreference::Cpu;
+    }
+}
 
-    // Run the detection pipeline
-    let result = run_detection(&config, input_path, output_path, matches.get_flag("save-heatmap"));
+/// Writes the fully-resolved `config` to `<output>.config.toml` and returns that path.
+fn save_run_config(config: &Config, output_path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let run_config_path = format!("{}.config.toml", output_path);
+    config.to_file(&run_config_path)?;
+    Ok(run_config_path)
+}
 
-    match result {
-        Ok(num_keypoints) => {
-            println!("✅ Successfully detected {} keypoints", num_keypoints);
-            println!("   Results saved to: {}", output_path);
-        }
-        Err(e) => {
-            eprintln!("❌ Error: {}", e);
-            std::process::exit(1);
-        }
-    }
+/// Command-line switches for a single-image run.
+struct RunOptions<'a> {
+    /// Run a throwaway forward pass before detection
+    warmup: bool,
+    /// Print the per-stage timing breakdown
+    timings: bool,
+    save_heatmap: bool,
+    /// Overlay the model-space keypoints on the saved heatmap
+    heatmap_keypoints: bool,
+    /// Color keypoints by confidence band instead of by score
+    bands: Option<&'a [f64]>,
+    report_path: Option<&'a str>,
+    exports: ExportFormats,
+}
 
-    Ok(())
+/// Keypoint files written next to each annotated output image.
+#[derive(Debug, Clone, Copy, Default)]
+struct ExportFormats {
+    /// `<output>.json`
+    json: bool,
+    /// `<output>.csv`
+    csv: bool,
+    /// `<output>.yml` in OpenCV FileStorage layout
+    opencv: bool,
+}
+
+/// Command-line switches for an `--input-dir` run.
+struct DirectoryOptions {
+    /// Images processed concurrently (per device with `devices`)
+    jobs: usize,
+    /// `--devices` value: `all` or a list of CUDA indices
+    devices: Option<String>,
+    warmup: bool,
+    exports: ExportFormats,
 }
 
+/// Single-image CLI run: a thin wrapper over `Detector` that adds reports, exports and drawing.
 fn run_detection(
     config: &Config,
     input_path: &str,
     output_path: &str,
-    save_heatmap: bool,
+    options: &RunOptions,
 ) -> Result<usize, SuperPointError> {
+    // Tiling and pyramid detection merge several passes, so there is no single heatmap
+    let multi_pass = config.tiling.is_some() || config.detection.is_multi_scale();
+    if multi_pass && (options.save_heatmap || options.bands.is_some()) {
+        return Err(SuperPointError::Config(
+            "--save-heatmap and --bands need single-pass detection; remove [tiling] and extra detection.scales"
+                .to_string(),
+        ));
+    }
+
     // 1. Initialize components
     info!("Initializing SuperPoint model...");
-    let model = SuperPointModel::new(config)?;
-    let device = model.device();
-    info!("Using device: {:?}", device);
-
-    let preprocessor = preprocessing::ImagePreprocessor::new(config.image.clone(), device);
-    let extractor = postprocessing::KeypointExtractor::new(config.keypoint.clone());
-    let visualizer = visualization::Visualizer::new(config.visualization.clone());
-
-    // 2. Load and preprocess image
-    info!("Loading and preprocessing image...");
-    let (input_tensor, original_image) = preprocessor.load_and_preprocess(input_path)?;
-    info!("Image preprocessed. Tensor shape: {:?}", input_tensor.size());
-
-    // 3. Run inference
-    info!("Running SuperPoint inference...");
-    let heatmap = model.infer(&input_tensor)?;
-    info!("Inference complete. Heatmap shape: {:?}", heatmap.size());
-
-    // 4. Extract keypoints
-    info!("Extracting keypoints...");
-    let keypoints_model_space = extractor.extract_keypoints(&heatmap)?;
-    info!("Found {} keypoints in model space", keypoints_model_space.len());
-
-    // 5. Scale keypoints to original image dimensions
-    let original_dims = original_image.dimensions();
-    let model_dims = (config.image.height, config.image.width);
-    let keypoints = extractor.scale_keypoints_to_original(
-        keypoints_model_space,
-        original_dims,
-        model_dims,
-    );
+    let detector = Detector::new(config)?;
+    info!("Using device: {:?}", detector.device());
+
+    if options.warmup {
+        info!("Running warm-up pass...");
+        detector.warmup()?;
+    }
+
+    let visualizer = detector.visualizer();
+
+    // 2-5. Preprocess, infer, extract and scale keypoints
+    info!("Loading {}...", input_path);
+    let original_image = image::open(input_path)
+        .map_err(|e| SuperPointError::ImageProcessing(format!("Failed to load image '{}': {}", input_path, e)))?;
+    let (keypoints, single_pass, mut timings) = if multi_pass {
+        let (keypoints, timings) = detector.detect_timed(&original_image)?;
+        (keypoints, None, timings)
+    } else {
+        let DetectionOutput { keypoints, keypoints_model_space, heatmap, timings, .. } =
+            detector.detect_full(&original_image)?;
+        (keypoints, Some((heatmap, keypoints_model_space)), timings)
+    };
+    info!("Found {} keypoints", keypoints.len());
+
+    if let Some(report_path) = options.report_path {
+        let report = Report::from_keypoints(config, &original_image, &keypoints, detector.device(), &timings);
+        report.to_file(report_path)?;
+        info!("Run report saved to {}", report_path);
+    }
+
+    // Exports carry undistorted positions when `[camera]` is set; drawing keeps the image's own
+    let exported = detector.undistort(&keypoints);
+    export_keypoints(config, &exported, original_image.dimensions(), Path::new(output_path), options.exports)?;
 
     // 6. Create visualization
     info!("Creating visualization...");
-    let result_image = visualizer.draw_keypoints_with_scores(&original_image, &keypoints)?;
+    let visualization_start = Instant::now();
+    let mut result_image = match (options.bands, &single_pass) {
+        (Some(thresholds), Some((heatmap, _))) => {
+            // Re-extract from the same heatmap, labelling keypoints by confidence band
+            let mut banded = Vec::new();
+            let mut labels = Vec::new();
+            for (label, (threshold, band)) in detector.detect_bands(&original_image, heatmap, thresholds)?.into_iter().enumerate() {
+                info!("Band > {}: {} keypoints", threshold, band.len());
+                labels.extend(std::iter::repeat_n(label as i32, band.len()));
+                banded.extend(band);
+            }
+            visualizer.draw_keypoints_by_label(&original_image, &banded, &labels)?
+        }
+        _ => visualizer.draw_keypoints_with_scores(&original_image, &keypoints)?,
+    };
+
+    if config.visualization.draw_nms_radius {
+        if let Some((radius_x, radius_y)) = detector.nms_radius_in_image(original_image.dimensions().into()) {
+            result_image = visualizer.draw_nms_radius(
+                &image::DynamicImage::ImageRgb8(result_image),
+                &keypoints,
+                radius_x,
+                radius_y,
+            )?;
+        } else {
+            warn!("--draw-nms-radius requested but nms_radius is not set");
+        }
+    }
+
+    timings.visualization = visualization_start.elapsed();
+
+    if options.timings {
+        println!("Timings:\n{}", timings);
+    }
+
     result_image.save(output_path)?;
 
     // 7. Optionally save heatmap visualization
-    if save_heatmap {
+    if options.save_heatmap
+        && let Some((heatmap, keypoints_model_space)) = &single_pass
+    {
         let heatmap_path = format!("{}_heatmap.png", output_path.trim_end_matches(".png"));
         info!("Saving heatmap visualization to {}...", heatmap_path);
-        let heatmap_vis = visualizer.create_heatmap_visualization(&heatmap)?;
+        let heatmap_vis = if options.heatmap_keypoints {
+            visualizer.create_heatmap_with_keypoints(heatmap, keypoints_model_space)?
+        } else {
+            visualizer.create_heatmap_visualization(heatmap)?
+        };
         heatmap_vis.save(&heatmap_path)?;
     }
 
     Ok(keypoints.len())
 }
+
+/// Annotates every image in `input_dir` into `output_dir` as `<stem>.png` (inputs sharing a
+/// stem are resolved per `batch.on_collision`), reusing one `Detector`, plus any requested
+/// keypoint exports. Images that fail to load, detect or save are skipped or abort the run per
+/// `batch.on_error`. Returns the manifest, which is also written to `output_dir`.
+///
+/// With `jobs > 1` images run on a rayon pool sharing that `Detector` (see `SuperPointModel`
+/// for why this is sound). On GPU the forward passes are serialized by the model while loading,
+/// pre/postprocessing and drawing overlap; on CPU each image also gets its own forward, with
+/// libtorch's intra-op threads divided between the jobs to avoid oversubscription.
+///
+/// With `devices`, one `Detector` is loaded per listed GPU and images are dealt round-robin to
+/// them, each device with its own thread and pool of `jobs`.
+///
+/// With the `signals` feature, Ctrl-C stops new images from starting; the ones in flight finish
+/// and the manifest lists them before the run returns.
+fn run_directory(
+    config: &Config,
+    input_dir: &Path,
+    output_dir: &Path,
+    options: &DirectoryOptions,
+) -> Result<batch::Manifest, SuperPointError> {
+    let image_paths = batch::image_paths(input_dir)?;
+    info!("Found {} images in {}", image_paths.len(), input_dir.display());
+    let items = batch::plan_outputs(image_paths, output_dir, config.batch.on_collision);
+
+    std::fs::create_dir_all(output_dir)?;
+
+    info!("Initializing SuperPoint model...");
+    let detectors = match &options.devices {
+        Some(devices) => model::cuda_devices(devices)?
+            .into_iter()
+            .map(|device| Detector::new_on_device(config, device))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => vec![Detector::new(config)?],
+    };
+    for detector in &detectors {
+        info!("Using device: {:?}", detector.device());
+        if options.warmup {
+            info!("Running warm-up pass...");
+            detector.warmup()?;
+        }
+    }
+
+    let jobs = options.jobs.max(1);
+    if jobs > 1 && detectors[0].device() == tch::Device::Cpu {
+        tch::set_num_threads((tch::get_num_threads() / jobs as i32).max(1));
+    }
+
+    let stop = interrupt_flag();
+    let manifest = batch::run_distributed(&items, &detectors, jobs, config.batch.on_error, &stop, |detector, item| {
+        let image = image::open(&item.input)
+            .map_err(|e| SuperPointError::ImageProcessing(format!("Failed to load image: {}", e)))?;
+        let (keypoints, annotated) = detector.detect_and_annotate(&image)?;
+
+        annotated.save(&item.output)?;
+        export_keypoints(config, &detector.undistort(&keypoints), image.dimensions(), &item.output, options.exports)?;
+        Ok(keypoints.len())
+    })?;
+
+    let manifest_path = output_dir.join(batch::MANIFEST_FILE);
+    manifest.to_file(&manifest_path)?;
+    info!("Manifest saved to {}", manifest_path.display());
+
+    if manifest.interrupted {
+        warn!(
+            "Interrupted: {} of {} images completed",
+            manifest.completed.len(),
+            items.len()
+        );
+    }
+
+    Ok(manifest)
+}
+
+/// Set once Ctrl-C is pressed (with the `signals` feature); never set otherwise.
+fn interrupt_flag() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+
+    #[cfg(feature = "signals")]
+    {
+        let handler_stop = Arc::clone(&stop);
+        if let Err(e) = ctrlc::set_handler(move || {
+            eprintln!("Interrupt received, finishing in-flight images...");
+            handler_stop.store(true, std::sync::atomic::Ordering::SeqCst);
+        }) {
+            warn!("Could not install the Ctrl-C handler: {}", e);
+        }
+    }
+
+    stop
+}
+
+/// Writes the requested keypoint files next to `output_path`, all sharing the configured
+/// sort order and precision.
+fn export_keypoints(
+    config: &Config,
+    keypoints: &[Keypoint],
+    image_dimensions: (u32, u32),
+    output_path: &Path,
+    formats: ExportFormats,
+) -> Result<(), SuperPointError> {
+    let exported = keypoint::prepare_for_export(keypoints, &config.export);
+
+    if formats.json {
+        let json_path = output_path.with_extension("json");
+        let json_path = json_path.to_string_lossy();
+        keypoint::export_json(
+            &exported,
+            image_dimensions,
+            config.keypoint.threshold,
+            config.export.descriptor_encoding,
+            &json_path,
+        )?;
+        info!("Keypoints exported to {}", json_path);
+    }
+
+    if formats.csv {
+        let csv_path = output_path.with_extension("csv");
+        let csv_path = csv_path.to_string_lossy();
+        keypoint::export_csv(&exported, &csv_path)?;
+        info!("Keypoints exported to {}", csv_path);
+    }
+
+    if formats.opencv {
+        let yaml_path = output_path.with_extension("yml");
+        let yaml_path = yaml_path.to_string_lossy();
+        keypoint::export_opencv_yaml(&exported, &yaml_path)?;
+        info!("Keypoints exported to {}", yaml_path);
+    }
+
+    Ok(())
+}
+
+/// Runs `Detector::detect_image` on every input with one shared `Detector` (so tiling, pyramid
+/// and every other detection setting apply) and renders the results as a grid.
+fn run_montage(
+    config: &Config,
+    input_paths: &[&String],
+    output_path: &str,
+    cols: usize,
+) -> Result<usize, SuperPointError> {
+    info!("Initializing SuperPoint model...");
+    let detector = Detector::new(config)?;
+    info!("Using device: {:?}", detector.device());
+
+    let mut images_with_keypoints = Vec::with_capacity(input_paths.len());
+    for input_path in input_paths {
+        info!("Detecting keypoints in {}...", input_path);
+        let original_image = image::open(input_path.as_str()).map_err(|e| {
+            SuperPointError::ImageProcessing(format!("Failed to load image '{}': {}", input_path, e))
+        })?;
+        let keypoints = detector.detect_image(&original_image)?;
+        info!("Found {} keypoints", keypoints.len());
+        images_with_keypoints.push((original_image, keypoints));
+    }
+
+    info!("Rendering {}-column montage...", cols);
+    let montage = detector.visualizer().render_montage(&images_with_keypoints, cols)?;
+    montage.save(output_path)?;
+
+    Ok(images_with_keypoints.iter().map(|(_, kps)| kps.len()).sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_config_records_cli_threshold_override() {
+        let output = std::env::temp_dir().join(format!("superpoint-{}-run.png", std::process::id()));
+        let output = output.to_str().unwrap();
+        let matches = cli()
+            .try_get_matches_from(["superpoint", "-i", "input.png", "-o", output, "-t", "0.25", "--save-run-config"])
+            .unwrap();
+        let mut config = Config::default();
+
+        apply_overrides(&mut config, &matches);
+        let run_config_path = save_run_config(&config, output).unwrap();
+
+        let saved = Config::from_file(&run_config_path).unwrap();
+        std::fs::remove_file(&run_config_path).unwrap();
+        assert_eq!(run_config_path, format!("{}.config.toml", output));
+        assert_eq!(saved.keypoint.threshold, 0.25);
+    }
+
+    #[test]
+    fn directory_mode_rejects_single_image_flags() {
+        for flag in [&["--report", "run.json"][..], &["--timings"], &["--save-heatmap"], &["--bands", "0.5,0.1"]] {
+            let args = [&["superpoint", "--input-dir", "images"][..], flag].concat();
+
+            let error = cli().try_get_matches_from(args).unwrap_err();
+
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict, "{:?}", flag);
+        }
+
+        assert!(cli().try_get_matches_from(["superpoint", "--input-dir", "images", "--export-json", "--warmup"]).is_ok());
+    }
+}
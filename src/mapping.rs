@@ -0,0 +1,105 @@
+use crate::keypoint::Keypoint;
+
+/// 3x3 homogeneous transform taking frame pixel coordinates into map coordinates.
+pub type Pose = [[f32; 3]; 3];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapPoint {
+    pub x: f32,
+    pub y: f32,
+    pub score: f32,
+    pub observations: usize,
+}
+
+/// Accumulates keypoints from posed frames, merging repeated observations of the same point.
+pub struct GlobalMap {
+    merge_radius: f32,
+    points: Vec<MapPoint>,
+}
+
+impl GlobalMap {
+    pub fn new(merge_radius: f32) -> Self {
+        Self {
+            merge_radius,
+            points: Vec::new(),
+        }
+    }
+
+    pub fn add_frame(&mut self, keypoints: &[Keypoint], pose: &Pose) {
+        // Only points that existed before this frame can absorb its keypoints,
+        // so two keypoints of one frame never collapse into a single map point
+        let existing = self.points.len();
+        let mut claimed = vec![false; existing];
+
+        for kp in keypoints {
+            let Some((x, y)) = project(pose, kp.x, kp.y) else {
+                continue;
+            };
+
+            let nearest = self.points[..existing]
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !claimed[*i])
+                .map(|(i, p)| (i, ((p.x - x).powi(2) + (p.y - y).powi(2)).sqrt()))
+                .filter(|&(_, d)| d <= self.merge_radius)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            match nearest {
+                Some((i, _)) => {
+                    claimed[i] = true;
+                    let point = &mut self.points[i];
+                    let n = point.observations as f32;
+
+                    // Running mean of the observed positions
+                    point.x = (point.x * n + x) / (n + 1.0);
+                    point.y = (point.y * n + y) / (n + 1.0);
+                    point.score = point.score.max(kp.score);
+                    point.observations += 1;
+                }
+                None => self.points.push(MapPoint {
+                    x,
+                    y,
+                    score: kp.score,
+                    observations: 1,
+                }),
+            }
+        }
+    }
+
+    pub fn points(&self) -> &[MapPoint] {
+        &self.points
+    }
+}
+
+fn project(pose: &Pose, x: f32, y: f32) -> Option<(f32, f32)> {
+    let w = pose[2][0] * x + pose[2][1] * y + pose[2][2];
+    if w.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let px = (pose[0][0] * x + pose[0][1] * y + pose[0][2]) / w;
+    let py = (pose[1][0] * x + pose[1][1] * y + pose[1][2]) / w;
+    Some((px, py))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: Pose = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    #[test]
+    fn repeated_observation_merges_into_one_point() {
+        let mut map = GlobalMap::new(2.0);
+        // The second frame is shifted 10 px right, so its (40, 20) is the map's (50, 20)
+        let shifted: Pose = [[1.0, 0.0, 10.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+        map.add_frame(&[Keypoint::new(50.0, 20.0, 0.4)], &IDENTITY);
+        map.add_frame(&[Keypoint::new(40.5, 20.0, 0.7)], &shifted);
+
+        assert_eq!(map.points().len(), 1);
+        let point = &map.points()[0];
+        assert_eq!(point.observations, 2);
+        assert_eq!((point.x, point.y, point.score), (50.25, 20.0, 0.7));
+    }
+}
@@ -0,0 +1,593 @@
+use crate::error::SuperPointError;
+use crate::keypoint::Keypoint;
+use std::io::Write;
+use std::path::Path;
+use tch::{Device, Kind, Tensor};
+
+/// Smallest consensus set accepted by `estimate_homography`.
+const MIN_HOMOGRAPHY_INLIERS: usize = 4;
+
+type Matrix3 = [[f64; 3]; 3];
+type Correspondence = ([f64; 2], [f64; 2]);
+
+// Every matcher measures Euclidean (L2) distance between descriptors. For the L2-normalized
+// output of `sample_descriptors` that is `sqrt(2 - 2 * cosine)`, so distances lie in [0, 2]
+// and a `max_distance` cap is given in those units. Matches farther apart than the cap are
+// dropped after the ratio or mutual check, so the cap never changes which neighbour is chosen.
+
+/// Brute-force matcher with Lowe's ratio test.
+///
+/// Computes the full L2 distance matrix between `desc1` (`[N, D]`) and `desc2` (`[M, D]`),
+/// takes the two nearest neighbours of every query descriptor and keeps the match only when
+/// `best < ratio * second_best` and, if set, `best <= max_distance`. Returns
+/// `(query_idx, train_idx, distance)` tuples; with fewer than two train descriptors the ratio
+/// test is undefined and no matches are returned. Fails if the results cannot be copied back
+/// from the descriptors' device.
+///
+/// To feed `Visualizer::draw_keypoint_matches`, drop the distance:
+/// `matches.iter().map(|&(q, t, _)| (q, t)).collect::<Vec<_>>()`.
+pub fn match_descriptors(
+    desc1: &Tensor,
+    desc2: &Tensor,
+    ratio: f32,
+    max_distance: Option<f32>,
+) -> Result<Vec<(usize, usize, f32)>, SuperPointError> {
+    let rows = desc1.size()[0].max(1) as usize;
+    match_descriptors_chunked(desc1, desc2, ratio, max_distance, rows)
+}
+
+/// `match_descriptors` over `match_chunk_size` query rows at a time.
+///
+/// Each block still sees every train descriptor, so the two nearest neighbours (and therefore
+/// the matches) are identical to the full-matrix path, while peak memory drops from `N x M`
+/// to `match_chunk_size x M` distances.
+pub fn match_descriptors_chunked(
+    desc1: &Tensor,
+    desc2: &Tensor,
+    ratio: f32,
+    max_distance: Option<f32>,
+    match_chunk_size: usize,
+) -> Result<Vec<(usize, usize, f32)>, SuperPointError> {
+    let rows = desc1.size()[0];
+    if rows == 0 || desc2.size()[0] < 2 {
+        return Ok(Vec::new());
+    }
+
+    let chunk = match_chunk_size.clamp(1, rows as usize) as i64;
+    let mut matches = Vec::new();
+
+    for start in (0..rows).step_by(chunk as usize) {
+        let block = desc1.narrow(0, start, chunk.min(rows - start));
+        let distances = distance_matrix(&block, desc2);
+        let (nearest, indices) = distances.topk(2, 1, false, true);
+        let best = nearest.select(1, 0);
+        let second = nearest.select(1, 1);
+
+        let query = best.lt_tensor(&(second * ratio as f64)).nonzero().view((-1,));
+        let train = indices.select(1, 0).index_select(0, &query);
+        let distance = best.index_select(0, &query);
+
+        matches.extend(collect_matches(&(query + start), &train, &distance)?);
+    }
+
+    Ok(within_max_distance(matches, max_distance))
+}
+
+/// Mutual nearest-neighbour matcher.
+///
+/// Keeps `(i, j)` only when `j` is the nearest neighbour of `i` in `desc2` and `i` is the nearest
+/// neighbour of `j` in `desc1`, and drops pairs farther apart than `max_distance` when set.
+/// The cross-check runs on the descriptors' device; only the final index pairs and distances
+/// are copied to the CPU.
+pub fn mutual_nn_match(
+    desc1: &Tensor,
+    desc2: &Tensor,
+    max_distance: Option<f32>,
+) -> Result<Vec<(usize, usize, f32)>, SuperPointError> {
+    if desc1.size()[0] == 0 || desc2.size()[0] == 0 {
+        return Ok(Vec::new());
+    }
+
+    let distances = distance_matrix(desc1, desc2);
+    let nn12 = distances.argmin(1, false);
+    let nn21 = distances.argmin(0, false);
+
+    let identity = Tensor::arange(distances.size()[0], (Kind::Int64, distances.device()));
+    let query = nn21.index_select(0, &nn12).eq_tensor(&identity).nonzero().view((-1,));
+    let train = nn12.index_select(0, &query);
+    let distance = distances
+        .index_select(0, &query)
+        .gather(1, &train.unsqueeze(1), false)
+        .view((-1,));
+
+    Ok(within_max_distance(collect_matches(&query, &train, &distance)?, max_distance))
+}
+
+fn within_max_distance(mut matches: Vec<(usize, usize, f32)>, max_distance: Option<f32>) -> Vec<(usize, usize, f32)> {
+    if let Some(max_distance) = max_distance {
+        matches.retain(|&(_, _, distance)| distance <= max_distance);
+    }
+    matches
+}
+
+fn distance_matrix(desc1: &Tensor, desc2: &Tensor) -> Tensor {
+    let desc1 = desc1.to_kind(Kind::Float);
+    let desc2 = desc2.to_kind(Kind::Float).to_device(desc1.device());
+    Tensor::cdist(&desc1, &desc2, 2.0, None::<i64>)
+}
+
+/// Moves the final index/distance triplets to the CPU and zips them into tuples.
+fn collect_matches(
+    query: &Tensor,
+    train: &Tensor,
+    distance: &Tensor,
+) -> Result<Vec<(usize, usize, f32)>, SuperPointError> {
+    let query: Vec<i64> = Vec::try_from(&query.to_device(Device::Cpu))?;
+    let train: Vec<i64> = Vec::try_from(&train.to_device(Device::Cpu))?;
+    let distance: Vec<f32> = Vec::try_from(&distance.to_device(Device::Cpu))?;
+
+    Ok(query
+        .into_iter()
+        .zip(train)
+        .zip(distance)
+        .map(|((q, t), d)| (q as usize, t as usize, d))
+        .collect())
+}
+
+/// Outcome of `estimate_homography`.
+#[derive(Debug, Clone)]
+pub struct HomographyResult {
+    /// Maps `kp1` coordinates onto `kp2`, normalized so `matrix[2][2] == 1`
+    pub matrix: [[f32; 3]; 3],
+    /// Index-aligned with the input `matches`
+    pub inliers: Vec<bool>,
+    pub inlier_count: usize,
+    /// Mean reprojection error of the inliers under `matrix`, in pixels
+    pub reprojection_error: f32,
+}
+
+/// Estimates the homography mapping `kp1` onto `kp2` with 4-point DLT RANSAC.
+///
+/// `matches` holds `(index into kp1, index into kp2)` pairs; a match is an inlier when its
+/// reprojection error is below `threshold` pixels. The best model is refit on all of its
+/// inliers and the inliers are re-marked under the refined matrix. Returns `None` for fewer
+/// than 4 matches or when no sample reaches `MIN_HOMOGRAPHY_INLIERS` inliers. Sampling uses a
+/// fixed seed, so results are reproducible.
+///
+/// To draw only consistent matches, keep `matches[i]` where `result.inliers[i]` is true.
+pub fn estimate_homography(
+    kp1: &[Keypoint],
+    kp2: &[Keypoint],
+    matches: &[(usize, usize)],
+    threshold: f32,
+    iterations: usize,
+) -> Option<HomographyResult> {
+    if matches.len() < 4 {
+        return None;
+    }
+
+    // Out-of-range indices can never be inliers
+    let correspondences: Vec<Option<Correspondence>> = matches
+        .iter()
+        .map(|&(i, j)| {
+            let (a, b) = (kp1.get(i)?, kp2.get(j)?);
+            Some(([a.x as f64, a.y as f64], [b.x as f64, b.y as f64]))
+        })
+        .collect();
+    let valid: Vec<Correspondence> = correspondences.iter().flatten().copied().collect();
+    if valid.len() < 4 {
+        return None;
+    }
+
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    let mut best: Option<(Matrix3, Vec<bool>, usize)> = None;
+
+    for _ in 0..iterations {
+        let mut sample = [0usize; 4];
+        let mut picked = 0;
+        while picked < 4 {
+            let candidate = rng.next_index(valid.len());
+            if !sample[..picked].contains(&candidate) {
+                sample[picked] = candidate;
+                picked += 1;
+            }
+        }
+
+        let Some(homography) = fit_homography(&sample.map(|i| valid[i])) else {
+            continue;
+        };
+        let inliers = mark_inliers(&homography, &correspondences, threshold as f64);
+        let count = inliers.iter().filter(|&&inlier| inlier).count();
+
+        if best.as_ref().is_none_or(|(_, _, best_count)| count > *best_count) {
+            best = Some((homography, inliers, count));
+        }
+    }
+
+    let (homography, inliers, count) = best?;
+    if count < MIN_HOMOGRAPHY_INLIERS {
+        return None;
+    }
+
+    let consensus: Vec<Correspondence> = correspondences
+        .iter()
+        .zip(&inliers)
+        .filter_map(|(correspondence, &inlier)| correspondence.filter(|_| inlier))
+        .collect();
+    let refined = fit_homography(&consensus).unwrap_or(homography);
+
+    let inliers = mark_inliers(&refined, &correspondences, threshold as f64);
+    let errors: Vec<f64> = correspondences
+        .iter()
+        .zip(&inliers)
+        .filter_map(|(correspondence, &inlier)| {
+            let (p1, p2) = correspondence.filter(|_| inlier)?;
+            reprojection_error(&refined, p1, p2)
+        })
+        .collect();
+    if errors.len() < MIN_HOMOGRAPHY_INLIERS {
+        return None;
+    }
+
+    Some(HomographyResult {
+        matrix: refined.map(|row| row.map(|v| v as f32)),
+        inlier_count: errors.len(),
+        reprojection_error: (errors.iter().sum::<f64>() / errors.len() as f64) as f32,
+        inliers,
+    })
+}
+
+/// Weighting used by `score_matches`. Each term maps linearly from 1 (perfect) to 0 at its cap.
+#[derive(Debug, Clone, Copy)]
+pub struct MatchScoring {
+    /// Share of the confidence taken by descriptor distance; the rest comes from reprojection error
+    pub distance_weight: f32,
+    /// Descriptor distance that scores 0 (2.0 is the largest L2 distance of normalized descriptors)
+    pub max_distance: f32,
+    /// Reprojection error in pixels that scores 0
+    pub max_reprojection_error: f32,
+}
+
+impl Default for MatchScoring {
+    fn default() -> Self {
+        Self {
+            distance_weight: 0.5,
+            max_distance: 2.0,
+            max_reprojection_error: 8.0,
+        }
+    }
+}
+
+/// Confidence in [0, 1] for each `(index into kp1, index into kp2, distance)` match, blending a
+/// low descriptor distance with a low reprojection error of `kp1` onto `kp2` under `homography`
+/// (e.g. `HomographyResult::matrix`). Matches that cannot be reprojected get no geometric credit.
+pub fn score_matches(
+    kp1: &[Keypoint],
+    kp2: &[Keypoint],
+    matches: &[(usize, usize, f32)],
+    homography: &[[f32; 3]; 3],
+    scoring: &MatchScoring,
+) -> Vec<f32> {
+    let homography: Matrix3 = homography.map(|row| row.map(f64::from));
+    let weight = scoring.distance_weight.clamp(0.0, 1.0);
+    let closeness = |value: f32, cap: f32| if cap > 0.0 { 1.0 - (value / cap).clamp(0.0, 1.0) } else { 0.0 };
+
+    matches
+        .iter()
+        .map(|&(i, j, distance)| {
+            let error = kp1.get(i).zip(kp2.get(j)).and_then(|(a, b)| {
+                reprojection_error(&homography, [a.x as f64, a.y as f64], [b.x as f64, b.y as f64])
+            });
+            let geometric = error.map_or(0.0, |error| closeness(error as f32, scoring.max_reprojection_error));
+            weight * closeness(distance, scoring.max_distance) + (1.0 - weight) * geometric
+        })
+        .collect()
+}
+
+/// Writes one image pair in the match-list format read by
+/// `colmap matches_importer --match_type inliers` (or `raw` when `inliers` is `None`):
+/// an `image1 image2` header line, one `index1 index2` line per match and a blank line.
+/// Files for several pairs can be concatenated into a single match list.
+///
+/// COLMAP resolves the indices against the keypoints already in its database, so import
+/// `kp1`/`kp2` first with `keypoint::export_colmap_features`, in the same order. Pass
+/// `Some(&result.inliers)` from `estimate_homography` to keep only verified matches. Matches
+/// whose indices fall outside `kp1`/`kp2` are skipped.
+pub fn export_colmap(
+    matches: &[(usize, usize)],
+    inliers: Option<&[bool]>,
+    kp1: &[Keypoint],
+    kp2: &[Keypoint],
+    image_names: (&str, &str),
+    path: impl AsRef<Path>,
+) -> Result<(), SuperPointError> {
+    if let Some(inliers) = inliers
+        && inliers.len() != matches.len()
+    {
+        return Err(SuperPointError::KeypointExtraction(format!(
+            "Inlier mask has {} entries for {} matches",
+            inliers.len(),
+            matches.len()
+        )));
+    }
+
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(writer, "{} {}", image_names.0, image_names.1)?;
+    for (k, &(i, j)) in matches.iter().enumerate() {
+        let verified = inliers.is_none_or(|inliers| inliers[k]);
+        if verified && i < kp1.len() && j < kp2.len() {
+            writeln!(writer, "{} {}", i, j)?;
+        }
+    }
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Marks each correspondence whose reprojection error under `homography` is below `threshold`.
+fn mark_inliers(homography: &Matrix3, correspondences: &[Option<Correspondence>], threshold: f64) -> Vec<bool> {
+    correspondences
+        .iter()
+        .map(|correspondence| {
+            correspondence.is_some_and(|(p1, p2)| {
+                reprojection_error(homography, p1, p2).is_some_and(|error| error < threshold)
+            })
+        })
+        .collect()
+}
+
+fn reprojection_error(homography: &Matrix3, p1: [f64; 2], p2: [f64; 2]) -> Option<f64> {
+    let [x, y] = apply(homography, p1)?;
+    Some(((x - p2[0]).powi(2) + (y - p2[1]).powi(2)).sqrt())
+}
+
+fn apply(homography: &Matrix3, [x, y]: [f64; 2]) -> Option<[f64; 2]> {
+    let h = homography;
+    let w = h[2][0] * x + h[2][1] * y + h[2][2];
+    if w.abs() < 1e-12 {
+        return None;
+    }
+    Some([
+        (h[0][0] * x + h[0][1] * y + h[0][2]) / w,
+        (h[1][0] * x + h[1][1] * y + h[1][2]) / w,
+    ])
+}
+
+/// Least-squares DLT (with `h33 = 1`) on Hartley-normalized points. Exact for four points.
+fn fit_homography(correspondences: &[Correspondence]) -> Option<Matrix3> {
+    if correspondences.len() < 4 {
+        return None;
+    }
+
+    let (t1, _) = normalizing_transform(correspondences.iter().map(|(p1, _)| *p1));
+    let (t2, t2_inv) = normalizing_transform(correspondences.iter().map(|(_, p2)| *p2));
+
+    // Normal equations AᵀA h = Aᵀb for the 8 unknowns
+    let mut ata = [[0.0f64; 8]; 8];
+    let mut atb = [0.0f64; 8];
+    for (p1, p2) in correspondences {
+        let [x, y] = apply(&t1, *p1)?;
+        let [u, v] = apply(&t2, *p2)?;
+        let rows = [
+            ([x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y], u),
+            ([0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y], v),
+        ];
+        for (row, rhs) in rows {
+            for (i, &ri) in row.iter().enumerate() {
+                for (j, &rj) in row.iter().enumerate() {
+                    ata[i][j] += ri * rj;
+                }
+                atb[i] += ri * rhs;
+            }
+        }
+    }
+
+    let h = solve_linear(ata, atb)?;
+    let normalized = [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], 1.0]];
+    let homography = mat_mul(&mat_mul(&t2_inv, &normalized), &t1);
+
+    let scale = homography[2][2];
+    if scale.abs() < 1e-12 {
+        return None;
+    }
+    Some(homography.map(|row| row.map(|v| v / scale)))
+}
+
+/// Similarity transform moving the points' centroid to the origin with mean distance √2,
+/// returned together with its inverse.
+fn normalizing_transform(points: impl Iterator<Item = [f64; 2]> + Clone) -> (Matrix3, Matrix3) {
+    let count = points.clone().count().max(1) as f64;
+    let (sx, sy) = points.clone().fold((0.0, 0.0), |(sx, sy), [x, y]| (sx + x, sy + y));
+    let (cx, cy) = (sx / count, sy / count);
+    let mean_distance = points.map(|[x, y]| ((x - cx).powi(2) + (y - cy).powi(2)).sqrt()).sum::<f64>() / count;
+    let scale = if mean_distance > 1e-12 { std::f64::consts::SQRT_2 / mean_distance } else { 1.0 };
+
+    (
+        [[scale, 0.0, -scale * cx], [0.0, scale, -scale * cy], [0.0, 0.0, 1.0]],
+        [[1.0 / scale, 0.0, cx], [0.0, 1.0 / scale, cy], [0.0, 0.0, 1.0]],
+    )
+}
+
+fn mat_mul(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+/// Gaussian elimination with partial pivoting; `None` if the system is singular.
+fn solve_linear(mut a: [[f64; 8]; 8], mut b: [f64; 8]) -> Option<[f64; 8]> {
+    for col in 0..8 {
+        let pivot = (col..8).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let pivot_row = a[col];
+        for row in (col + 1)..8 {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, pivot_value) in a[row].iter_mut().zip(pivot_row).skip(col) {
+                *value -= factor * pivot_value;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0f64; 8];
+    for row in (0..8).rev() {
+        let tail: f64 = ((row + 1)..8).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Minimal xorshift64 generator so RANSAC needs no external RNG and stays reproducible.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_index(&mut self, len: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % len as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptors(rows: &[[f32; 2]]) -> Tensor {
+        let values: Vec<f32> = rows.iter().flatten().copied().collect();
+        Tensor::from_slice(&values).view((rows.len() as i64, 2))
+    }
+
+    #[test]
+    fn max_distance_drops_distant_matches() {
+        // Both pairs are mutual nearest neighbours: (0, 0) at distance 0, (1, 1) at ~0.63
+        let desc1 = descriptors(&[[1.0, 0.0], [0.0, 1.0]]);
+        let desc2 = descriptors(&[[1.0, 0.0], [0.6, 0.8]]);
+
+        assert_eq!(mutual_nn_match(&desc1, &desc2, None).unwrap().len(), 2);
+
+        let capped = mutual_nn_match(&desc1, &desc2, Some(0.5)).unwrap();
+        assert_eq!(capped.len(), 1);
+        assert_eq!((capped[0].0, capped[0].1), (0, 0));
+    }
+
+    #[test]
+    fn geometric_consistency_raises_match_confidence() {
+        let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        let kp1 = [Keypoint::new(10.0, 10.0, 1.0), Keypoint::new(50.0, 50.0, 1.0)];
+        let kp2 = [Keypoint::new(90.0, 10.0, 1.0), Keypoint::new(51.0, 50.0, 1.0)];
+        // The first match has the better descriptor but lands 80 px away from its reprojection
+        let matches = [(0, 0, 0.1), (1, 1, 0.4)];
+
+        let scores = score_matches(&kp1, &kp2, &matches, &identity, &MatchScoring::default());
+
+        assert!(scores.iter().all(|score| (0.0..=1.0).contains(score)));
+        assert!(scores[1] > scores[0], "{:?}", scores);
+    }
+
+    #[test]
+    fn ratio_test_rejects_ambiguous_matches() {
+        // Query 0 is equidistant from both train descriptors; query 1 is clearly closer to train 0
+        let desc1 = descriptors(&[[1.0, 0.0], [0.0, 1.0]]);
+        let desc2 = descriptors(&[[0.6, 0.8], [0.6, -0.8]]);
+
+        let matches = match_descriptors(&desc1, &desc2, 0.8, None).unwrap();
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].0, matches[0].1), (1, 0));
+        assert!((matches[0].2 - 0.4f32.sqrt()).abs() < 1e-5);
+        assert_eq!(match_descriptors_chunked(&desc1, &desc2, 0.8, None, 1).unwrap(), matches);
+    }
+
+    #[test]
+    fn mutual_check_keeps_only_reciprocal_pairs() {
+        // B0 is the nearest neighbour of both A0 and A1 but only picks A0 back; A2 <-> B1 is mutual
+        let desc1 = descriptors(&[[1.0, 0.0], [0.8, 0.6], [0.0, 1.0]]);
+        let desc2 = descriptors(&[[1.0, 0.0], [0.0, 1.0]]);
+
+        let pairs: Vec<(usize, usize)> = mutual_nn_match(&desc1, &desc2, None)
+            .unwrap()
+            .iter()
+            .map(|&(i, j, _)| (i, j))
+            .collect();
+
+        assert_eq!(pairs, vec![(0, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn chunked_matching_equals_full_matrix() {
+        tch::manual_seed(7);
+        let desc1 = Tensor::randn([300, 32], (Kind::Float, Device::Cpu));
+        let desc2 = Tensor::randn([400, 32], (Kind::Float, Device::Cpu));
+
+        let full = match_descriptors(&desc1, &desc2, 0.9, None).unwrap();
+        assert!(!full.is_empty());
+
+        for chunk in [1, 37, 128, 1000] {
+            let chunked = match_descriptors_chunked(&desc1, &desc2, 0.9, None, chunk).unwrap();
+            assert_eq!(chunked.len(), full.len(), "chunk size {}", chunk);
+            for (a, b) in chunked.iter().zip(&full) {
+                assert_eq!((a.0, a.1), (b.0, b.1), "chunk size {}", chunk);
+                assert!((a.2 - b.2).abs() < 1e-4, "chunk size {}", chunk);
+            }
+        }
+    }
+
+    const TRUE_HOMOGRAPHY: Matrix3 = [[1.1, 0.05, 12.0], [-0.04, 0.95, -7.0], [0.0002, -0.0001, 1.0]];
+
+    /// `kp2` is `kp1` under `TRUE_HOMOGRAPHY`, except the matches listed in `outliers`.
+    fn homography_scene(outliers: &[usize]) -> (Vec<Keypoint>, Vec<Keypoint>, Vec<(usize, usize)>) {
+        let kp1: Vec<Keypoint> = (0..30)
+            .map(|i| Keypoint::new((i % 6) as f32 * 50.0 + 10.0, (i / 6) as f32 * 45.0 + 5.0, 1.0))
+            .collect();
+        let kp2 = kp1
+            .iter()
+            .enumerate()
+            .map(|(i, kp)| {
+                let [x, y] = apply(&TRUE_HOMOGRAPHY, [kp.x as f64, kp.y as f64]).unwrap();
+                let shift = if outliers.contains(&i) { 80.0 } else { 0.0 };
+                Keypoint::new((x + shift) as f32, (y - shift) as f32, 1.0)
+            })
+            .collect();
+        let matches = (0..30).map(|i| (i, i)).collect();
+        (kp1, kp2, matches)
+    }
+
+    #[test]
+    fn ransac_recovers_homography_despite_outliers() {
+        let (kp1, kp2, matches) = homography_scene(&[3, 11, 17, 25]);
+
+        let result = estimate_homography(&kp1, &kp2, &matches, 2.0, 200).unwrap();
+
+        for (row, truth_row) in result.matrix.iter().zip(TRUE_HOMOGRAPHY) {
+            for (value, expected) in row.iter().zip(truth_row) {
+                assert!((*value as f64 - expected).abs() < 1e-3, "{:?}", result.matrix);
+            }
+        }
+        assert!(estimate_homography(&kp1, &kp2, &matches[..3], 2.0, 200).is_none());
+    }
+
+    #[test]
+    fn inlier_mask_aligns_with_matches() {
+        let outliers = [3, 11, 17, 25];
+        let (kp1, kp2, matches) = homography_scene(&outliers);
+
+        let result = estimate_homography(&kp1, &kp2, &matches, 2.0, 200).unwrap();
+
+        assert_eq!(result.inliers.len(), matches.len());
+        for (i, &inlier) in result.inliers.iter().enumerate() {
+            assert_eq!(inlier, !outliers.contains(&i), "match {}", i);
+        }
+        assert_eq!(result.inlier_count, matches.len() - outliers.len());
+        assert!(result.reprojection_error < 1e-2, "{}", result.reprojection_error);
+    }
+}
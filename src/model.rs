@@ -1,29 +1,125 @@
 use crate::error::SuperPointError;
-use crate::config::{Config, ModelConfig};
-use tch::{CModule, Device, IValue, Kind, Tensor};
+use crate::config::{Config, DevicePreference, ModelConfig, Precision};
+use log::{info, warn};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use tch::{CModule, Cuda, Device, IValue, Kind, TchError, Tensor};
 
+/// SuperPoint's cell size: each semi-dense head position covers an 8x8 pixel block.
+const CELL_SIZE: i64 = 8;
+
+/// Optional facts a TorchScript export can carry about itself.
+///
+/// Integer fields are read from zero-argument methods of the same name exported
+/// with `@torch.jit.export`; `descriptor_dim` comes from the `convDb.weight`
+/// parameter of the reference SuperPoint architecture. Plain module attributes and
+/// registered buffers are not read: tch's `CModule` exposes no attribute lookup, so an
+/// export has to wrap each value in a getter (see `tests/fixtures/make_metadata_fixture.py`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelMetadata {
+    pub input_height: Option<i64>,
+    pub input_width: Option<i64>,
+    pub cell_size: Option<i64>,
+    pub descriptor_dim: Option<i64>,
+}
+
+/// `SuperPointModel` is `Send + Sync` and can be shared across threads: tch marks `CModule`
+/// as both, and a TorchScript forward in inference mode does not mutate the module. Tensors
+/// are only `Send`, so every thread works on its own inputs and outputs.
 pub struct SuperPointModel {
     model: CModule,
     device: Device,
     config: ModelConfig,
+    /// Configured `(height, width)` of the model input
+    input_size: (i64, i64),
+    /// Serializes forward passes on GPU devices; concurrent launches there only compete for
+    /// memory, while CPU forwards may run in parallel
+    gpu_lock: Mutex<()>,
+    /// CPU copy of the model for `oom_fallback_cpu`, loaded on the first out-of-memory retry
+    cpu_model: OnceLock<CModule>,
 }
 
 impl SuperPointModel {
     pub fn new(config: &Config) -> Result<Self, SuperPointError> {
-        let device = if config.model.use_cuda && Device::cuda_if_available() != Device::Cpu {
-            Device::cuda_if_available()
-        } else {
-            Device::Cpu
-        };
+        Self::new_on_device(config, select_device(&config.model)?)
+    }
 
-        let model = CModule::load_on_device(&config.model.path, device)
+    /// Loads the model onto `device`, ignoring `use_cuda`/`device_preference`. Used to place one
+    /// copy on each GPU for multi-device runs.
+    pub fn new_on_device(config: &Config, device: Device) -> Result<Self, SuperPointError> {
+        let mut model = CModule::load_on_device(&config.model.path, device)
             .map_err(|e| SuperPointError::ModelLoading(format!("{}", e)))?;
 
-        Ok(Self {
+        // Half precision is only worthwhile (and well supported) on GPU backends
+        if config.model.precision == Precision::Float16 {
+            if device == Device::Cpu {
+                return Err(SuperPointError::ModelLoading(
+                    "Float16 precision requires a CUDA or MPS device".to_string(),
+                ));
+            }
+            model.to(device, Kind::Half, false);
+        }
+
+        let (height, width) = (config.image.height, config.image.width);
+        if height % CELL_SIZE != 0 || width % CELL_SIZE != 0 {
+            info!(
+                "Input {}x{} is not a multiple of the {}-pixel cell size; it will be edge-padded to {}x{} \
+                 and the heatmap cropped back",
+                width,
+                height,
+                CELL_SIZE,
+                padded_len(width),
+                padded_len(height)
+            );
+        }
+
+        let model = Self {
             model,
             device,
             config: config.model.clone(),
-        })
+            input_size: (config.image.height, config.image.width),
+            gpu_lock: Mutex::new(()),
+            cpu_model: OnceLock::new(),
+        };
+        model.warn_on_metadata_mismatch(config);
+
+        Ok(model)
+    }
+
+    /// Runs one throwaway `infer` on a zero image of the configured input size, so lazy kernel
+    /// compilation and allocator warm-up do not land on the first real inference. Going through
+    /// `infer` exercises the same padding, precision cast and post-forward kernels.
+    pub fn warmup(&self) -> Result<(), SuperPointError> {
+        let (height, width) = self.input_size;
+        let input = Tensor::zeros([1, 1, height, width], (Kind::Float, self.device));
+        let _heatmap = self.infer(&input)?;
+
+        Ok(())
+    }
+
+    pub fn metadata(&self) -> ModelMetadata {
+        let descriptor_dim = self
+            .model
+            .named_parameters()
+            .ok()
+            .and_then(|params| {
+                params
+                    .into_iter()
+                    .find(|(name, _)| name == "convDb.weight")
+                    .map(|(_, weight)| weight.size()[0])
+            });
+
+        ModelMetadata {
+            input_height: int_getter(&self.model, "input_height"),
+            input_width: int_getter(&self.model, "input_width"),
+            cell_size: int_getter(&self.model, "cell_size"),
+            descriptor_dim,
+        }
+    }
+
+    fn warn_on_metadata_mismatch(&self, config: &Config) {
+        for mismatch in metadata_mismatches(&self.metadata(), config) {
+            warn!("{}", mismatch);
+        }
     }
 
     pub fn device(&self) -> Device {
@@ -31,6 +127,53 @@ impl SuperPointModel {
     }
 
     pub fn infer(&self, input_tensor: &Tensor) -> Result<Tensor, SuperPointError> {
+        let (semi, _) = self.run_heads(input_tensor)?;
+        self.semi_to_heatmap(semi, spatial_size(input_tensor))
+    }
+
+    /// Returns the softmaxed `[64, Hc, Wc]` cell probabilities with the dustbin removed, before
+    /// depth-to-space. `Hc`/`Wc` cover the input padded up to whole 8-pixel cells;
+    /// `depth_to_space(cells, 8)` turns the result into the (uncropped) heatmap.
+    pub fn infer_raw(&self, input_tensor: &Tensor) -> Result<Tensor, SuperPointError> {
+        let (semi, _) = self.run_heads(input_tensor)?;
+        self.semi_to_cells(semi)
+    }
+
+    /// Batched variant of `infer`: `[N, 1, H, W]` input to `[N, H, W]` heatmaps.
+    pub fn infer_batch(&self, input_tensor: &Tensor) -> Result<Tensor, SuperPointError> {
+        let (semi, _) = self.run_heads(input_tensor)?;
+        if semi.dim() != 4 {
+            return Err(SuperPointError::Inference(format!(
+                "Expected batched semi-heatmap [N, 65, Hc, Wc], got {:?}",
+                semi.size()
+            )));
+        }
+
+        let heatmaps = (0..semi.size()[0])
+            .map(|i| self.semi_to_heatmap(semi.get(i), spatial_size(input_tensor)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Tensor::stack(&heatmaps, 0))
+    }
+
+    /// Returns the `[H, W]` score map together with the raw `[1, D, Hc, Wc]` descriptor
+    /// tensor from the model's second output, left at full precision on the model device.
+    /// For inputs that are not a multiple of 8 the descriptor map covers the padded input.
+    pub fn infer_with_descriptors(&self, input_tensor: &Tensor) -> Result<(Tensor, Tensor), SuperPointError> {
+        let (semi, descriptors) = self.run_heads(input_tensor)?;
+        let descriptors = descriptors.ok_or_else(|| {
+            SuperPointError::Inference(
+                "Model output has no descriptor tensor at tuple index 1".to_string(),
+            )
+        })?;
+
+        Ok((self.semi_to_heatmap(semi, spatial_size(input_tensor))?, descriptors))
+    }
+
+    /// Runs the network and splits its output into the semi-dense head and, when the
+    /// model returns a tuple with a second element, the descriptor head. Inputs whose height
+    /// or width is not a multiple of 8 are edge-padded on the bottom/right first.
+    fn run_heads(&self, input_tensor: &Tensor) -> Result<(Tensor, Option<Tensor>), SuperPointError> {
         // Validate input tensor dimensions
         let input_dims = input_tensor.size();
         if input_dims.len() != 4 || input_dims[1] != 1 {
@@ -40,32 +183,62 @@ impl SuperPointModel {
             )));
         }
 
+        // Pad up to whole cells; replicating the edge avoids a spurious corner at the seam
+        let (height, width) = spatial_size(input_tensor);
+        let (pad_h, pad_w) = (padded_len(height) - height, padded_len(width) - width);
+        let input_tensor = if pad_h > 0 || pad_w > 0 {
+            input_tensor.f_replication_pad2d([0, pad_w, 0, pad_h])?
+        } else {
+            input_tensor.shallow_clone()
+        };
+
         // Run inference
-        let output_ival: IValue = self
-            .model
-            .forward_is(&[IValue::Tensor(input_tensor.shallow_clone())])
-            .map_err(|e| SuperPointError::Inference(format!("Forward pass failed: {}", e)))?;
-
-        // Extract the semi-dense heatmap tensor
-        let semi: Tensor = match output_ival {
-            IValue::Tuple(ref ivals) if !ivals.is_empty() => match &ivals[0] {
-                IValue::Tensor(t0) => t0.shallow_clone(),
-                other => {
-                    return Err(SuperPointError::Inference(format!(
-                        "Expected Tensor at tuple index 0, found: {:?}",
-                        other
-                    )));
-                }
-            },
-            IValue::Tensor(t) => t.shallow_clone(),
-            other => {
-                return Err(SuperPointError::Inference(format!(
-                    "Unexpected IValue from forward: {:?}. Expected Tensor or Tuple(Tensor,…).",
-                    other
-                )));
+        let output_ival = self.forward_raw(&input_tensor)?;
+
+        match output_ival {
+            IValue::Tuple(ref ivals) if !ivals.is_empty() => {
+                // Heads come back in the model's precision; everything downstream is f32
+                let semi = match &ivals[0] {
+                    IValue::Tensor(t0) => t0.to_kind(Kind::Float),
+                    other => {
+                        return Err(SuperPointError::Inference(format!(
+                            "Expected Tensor at tuple index 0, found: {:?}",
+                            other
+                        )));
+                    }
+                };
+
+                let descriptors = match ivals.get(1) {
+                    Some(IValue::Tensor(t1)) => Some(t1.to_kind(Kind::Float)),
+                    Some(other) => {
+                        return Err(SuperPointError::Inference(format!(
+                            "Expected descriptor Tensor at tuple index 1, found: {:?}",
+                            other
+                        )));
+                    }
+                    None => None,
+                };
+
+                Ok((semi, descriptors))
             }
-        };
+            IValue::Tensor(t) => Ok((t.to_kind(Kind::Float), None)),
+            other => Err(SuperPointError::Inference(format!(
+                "Unexpected IValue from forward: {:?}. Expected Tensor or Tuple(Tensor,…).",
+                other
+            ))),
+        }
+    }
+
+    /// Converts a semi-dense head to a score map cropped to `(height, width)`, the unpadded input size.
+    fn semi_to_heatmap(&self, semi: Tensor, (height, width): (i64, i64)) -> Result<Tensor, SuperPointError> {
+        let heatmap = depth_to_space(&self.semi_to_cells(semi)?, CELL_SIZE)?;
+
+        // Drop the rows/columns that only exist because of cell padding
+        Ok(heatmap.narrow(0, 0, height).narrow(1, 0, width))
+    }
 
+    /// Softmax over the 65 channels of a semi-dense head, keeping the 64 non-dustbin cells.
+    fn semi_to_cells(&self, semi: Tensor) -> Result<Tensor, SuperPointError> {
         // Ensure proper dimensions and squeeze batch dimension if needed
         let semi = if semi.dim() == 4 && semi.size()[0] == 1 {
             semi.squeeze_dim(0)
@@ -82,15 +255,367 @@ impl SuperPointModel {
         let prob = semi.softmax(0, Kind::Float);
 
         // Remove dustbin channel (last channel)
-        let prob_cells = prob.narrow(0, 0, 64);
+        Ok(prob.narrow(0, 0, CELL_SIZE * CELL_SIZE))
+    }
+
+    /// Runs only the network and returns its output untouched (no softmax or depth-to-space).
+    pub fn forward_raw(&self, input_tensor: &Tensor) -> Result<IValue, SuperPointError> {
+        let input = match self.config.precision {
+            Precision::Float32 => input_tensor.shallow_clone(),
+            Precision::Float16 => input_tensor.to_kind(Kind::Half),
+        };
+        with_oom_fallback(
+            self.config.oom_fallback_cpu && self.device.is_cuda(),
+            || {
+                let _guard = (self.device != Device::Cpu)
+                    .then(|| self.gpu_lock.lock().unwrap_or_else(PoisonError::into_inner));
+                self.model.forward_is(&[IValue::Tensor(input)])
+            },
+            || self.forward_on_cpu(input_tensor),
+        )
+    }
+
+    fn forward_on_cpu(&self, input_tensor: &Tensor) -> Result<IValue, SuperPointError> {
+        let cpu_model = match self.cpu_model.get() {
+            Some(model) => model,
+            None => {
+                let model = CModule::load_on_device(&self.config.path, Device::Cpu)
+                    .map_err(|e| SuperPointError::ModelLoading(format!("{}", e)))?;
+                // A concurrent fallback may have won the race; either copy is equivalent
+                self.cpu_model.get_or_init(|| model)
+            }
+        };
+
+        cpu_model
+            .forward_is(&[IValue::Tensor(input_tensor.to_device(Device::Cpu).to_kind(Kind::Float))])
+            .map_err(|e| SuperPointError::Inference(format!("CPU fallback forward pass failed: {}", e)))
+    }
+}
+
+/// Calls the zero-argument `@torch.jit.export` method `name`; a missing method or a
+/// non-integer result reads as absent.
+fn int_getter(module: &CModule, name: &str) -> Option<i64> {
+    match module.method_is(name, &[] as &[IValue]) {
+        Ok(IValue::Int(value)) => Some(value),
+        _ => None,
+    }
+}
 
-        // Depth-to-space transformation
-        let reshaped = prob_cells
-            .view((8, 8, prob_cells.size()[1], prob_cells.size()[2]))
-            .permute(&[2i64, 0, 3, 1])
-            .contiguous()
-            .view((prob_cells.size()[1] * 8, prob_cells.size()[2] * 8));
+/// Describes every metadata value that disagrees with `config`; absent values never do.
+fn metadata_mismatches(metadata: &ModelMetadata, config: &Config) -> Vec<String> {
+    let mut mismatches = Vec::new();
 
-        Ok(reshaped)
+    if let Some(height) = metadata.input_height
+        && height != config.image.height
+    {
+        mismatches.push(format!("Model expects input height {} but config uses {}", height, config.image.height));
     }
-} 
\ No newline at end of file
+    if let Some(width) = metadata.input_width
+        && width != config.image.width
+    {
+        mismatches.push(format!("Model expects input width {} but config uses {}", width, config.image.width));
+    }
+    if let Some(cell_size) = metadata.cell_size
+        && cell_size != CELL_SIZE
+    {
+        mismatches.push(format!("Model reports cell size {} but depth-to-space assumes {}", cell_size, CELL_SIZE));
+    }
+
+    mismatches
+}
+
+/// Runs `forward`; when it fails with an out-of-memory error and `fallback_enabled` is set,
+/// logs a warning and returns the result of `fallback` instead.
+fn with_oom_fallback<T>(
+    fallback_enabled: bool,
+    forward: impl FnOnce() -> Result<T, TchError>,
+    fallback: impl FnOnce() -> Result<T, SuperPointError>,
+) -> Result<T, SuperPointError> {
+    match forward() {
+        Ok(output) => Ok(output),
+        Err(e) if fallback_enabled && e.to_string().contains("out of memory") => {
+            warn!("CUDA out of memory during inference, retrying on CPU");
+            fallback()
+        }
+        Err(e) => Err(SuperPointError::Inference(format!("Forward pass failed: {}", e))),
+    }
+}
+
+/// Rearranges `[cell_size², Hc, Wc]` cell probabilities into an `[Hc * cell_size, Wc * cell_size]`
+/// heatmap.
+pub fn depth_to_space(cells: &Tensor, cell_size: i64) -> Result<Tensor, SuperPointError> {
+    let dims = cells.size();
+    if cell_size <= 0 || dims.len() != 3 || dims[0] != cell_size * cell_size {
+        return Err(SuperPointError::Inference(format!(
+            "Expected cells [{}, Hc, Wc] for cell size {}, got {:?}",
+            cell_size * cell_size,
+            cell_size,
+            dims
+        )));
+    }
+    let (coarse_height, coarse_width) = (dims[1], dims[2]);
+
+    // Channel c holds in-cell row c / cell_size and column c % cell_size, so [c, Hc, Wc] views
+    // as [row, col, Hc, Wc] and permutes to [Hc, row, Wc, col]: pixel (Hc * s + row, Wc * s + col).
+    // This is the reference implementation's transpose(1, 2, 0) -> reshape(Hc, Wc, s, s) ->
+    // transpose(0, 2, 1, 3) -> reshape(Hc * s, Wc * s); x and y are not swapped.
+    Ok(cells
+        .view((cell_size, cell_size, coarse_height, coarse_width))
+        .permute([2i64, 0, 3, 1])
+        .contiguous()
+        .view((coarse_height * cell_size, coarse_width * cell_size)))
+}
+
+/// `(height, width)` from the last two dimensions of a tensor.
+fn spatial_size(tensor: &Tensor) -> (i64, i64) {
+    let dims = tensor.size();
+    (dims[dims.len() - 2], dims[dims.len() - 1])
+}
+
+/// Rounds `len` up to the next whole number of cells.
+fn padded_len(len: i64) -> i64 {
+    (len + CELL_SIZE - 1) / CELL_SIZE * CELL_SIZE
+}
+
+/// Parses a `--devices` value: `all` for every visible CUDA device, or a comma-separated list
+/// of CUDA indices such as `0,2`.
+pub fn cuda_devices(spec: &str) -> Result<Vec<Device>, SuperPointError> {
+    let available = Cuda::device_count() as usize;
+    let indices: Vec<usize> = if spec.trim() == "all" {
+        (0..available).collect()
+    } else {
+        spec.split(',')
+            .map(|index| {
+                index.trim().parse::<usize>().map_err(|_| {
+                    SuperPointError::Config(format!("Invalid CUDA device index '{}' in --devices", index))
+                })
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    if indices.is_empty() {
+        return Err(SuperPointError::ModelLoading("--devices found no CUDA devices".to_string()));
+    }
+    if let Some(&index) = indices.iter().find(|&&index| index >= available) {
+        return Err(SuperPointError::ModelLoading(format!(
+            "CUDA device {} requested but only {} available",
+            index, available
+        )));
+    }
+
+    Ok(indices.into_iter().map(Device::Cuda).collect())
+}
+
+/// Resolves `device_preference` to a concrete device. Forcing a backend that is not available
+/// is an error rather than a silent CPU fallback.
+fn select_device(config: &ModelConfig) -> Result<Device, SuperPointError> {
+    match config.device_preference {
+        DevicePreference::Auto => Ok(if config.use_cuda && Cuda::is_available() {
+            Device::Cuda(0)
+        } else if config.use_cuda && tch::utils::has_mps() {
+            Device::Mps
+        } else {
+            Device::Cpu
+        }),
+        DevicePreference::Cuda if Cuda::is_available() => Ok(Device::Cuda(0)),
+        DevicePreference::Mps if tch::utils::has_mps() => Ok(Device::Mps),
+        DevicePreference::Cpu => Ok(Device::Cpu),
+        preference => Err(SuperPointError::ModelLoading(format!(
+            "Device preference {:?} requested but that backend is not available",
+            preference
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn cuda_oom() -> Result<&'static str, TchError> {
+        Err(TchError::Torch("CUDA out of memory. Tried to allocate 2.00 GiB".to_string()))
+    }
+
+    #[test]
+    fn oom_retries_on_cpu_when_enabled() {
+        let retried = Cell::new(false);
+
+        let output = with_oom_fallback(true, cuda_oom, || {
+            retried.set(true);
+            Ok("cpu")
+        });
+
+        assert!(retried.get());
+        assert_eq!(output.unwrap(), "cpu");
+    }
+
+    #[test]
+    fn oom_fails_when_fallback_disabled() {
+        let retried = Cell::new(false);
+
+        let output = with_oom_fallback(false, cuda_oom, || {
+            retried.set(true);
+            Ok("cpu")
+        });
+
+        assert!(!retried.get());
+        assert!(matches!(output, Err(SuperPointError::Inference(_))));
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let retried = Cell::new(false);
+
+        let output = with_oom_fallback(
+            true,
+            || Err::<&str, _>(TchError::Torch("Expected 4-dimensional input".to_string())),
+            || {
+                retried.set(true);
+                Ok("cpu")
+            },
+        );
+
+        assert!(!retried.get());
+        assert!(output.is_err());
+    }
+
+    fn cpu_model() -> SuperPointModel {
+        let mut config = Config::default();
+        config.model.use_cuda = false;
+        SuperPointModel::new(&config).expect("superpoint_v2.pt should load on CPU")
+    }
+
+    #[test]
+    fn forward_raw_returns_unprocessed_heads() {
+        let model = cpu_model();
+        let input = Tensor::zeros([1, 1, 240, 320], (Kind::Float, Device::Cpu));
+
+        let semi = match model.forward_raw(&input).unwrap() {
+            IValue::Tuple(heads) => match heads.into_iter().next() {
+                Some(IValue::Tensor(semi)) => semi,
+                other => panic!("expected a tensor head, got {:?}", other),
+            },
+            IValue::Tensor(semi) => semi,
+            other => panic!("expected a tensor or tuple, got {:?}", other),
+        };
+
+        // 65 logits per 8x8 cell: no softmax, dustbin removal or depth-to-space
+        assert_eq!(semi.size(), vec![1, 65, 30, 40]);
+        let channel_sums = semi.sum_dim_intlist(1, false, Kind::Float);
+        assert!(!channel_sums.allclose(&channel_sums.ones_like(), 1e-3, 1e-3, false));
+    }
+
+    #[test]
+    fn metadata_surfaces_descriptor_dim_and_defaults_missing_attributes() {
+        let metadata = cpu_model().metadata();
+
+        // superpoint_v2.pt has the reference convDb layer but exports no integer attributes
+        assert_eq!(metadata.descriptor_dim, Some(256));
+        assert_eq!((metadata.input_height, metadata.input_width, metadata.cell_size), (None, None, None));
+    }
+
+    #[test]
+    #[ignore = "needs tests/fixtures/metadata_getters.pt from make_metadata_fixture.py"]
+    fn metadata_reads_exported_getters_from_fixture_module() {
+        let module = CModule::load("tests/fixtures/metadata_getters.pt").unwrap();
+
+        assert_eq!(int_getter(&module, "input_height"), Some(240));
+        assert_eq!(int_getter(&module, "cell_size"), Some(8));
+        // Stored as a plain attribute without a getter, so it is not visible through tch
+        assert_eq!(int_getter(&module, "input_width"), None);
+    }
+
+    #[test]
+    fn metadata_mismatches_are_reported() {
+        let config = Config::default();
+        let metadata = ModelMetadata {
+            input_height: Some(480),
+            input_width: Some(config.image.width),
+            cell_size: Some(8),
+            descriptor_dim: Some(256),
+        };
+
+        let mismatches = metadata_mismatches(&metadata, &config);
+
+        assert_eq!(mismatches, vec!["Model expects input height 480 but config uses 240".to_string()]);
+        assert!(metadata_mismatches(&ModelMetadata::default(), &config).is_empty());
+    }
+
+    #[test]
+    fn infer_with_descriptors_keeps_raw_descriptor_head() {
+        let model = cpu_model();
+        let input = Tensor::rand([1, 1, 240, 320], (Kind::Float, Device::Cpu));
+
+        let (heatmap, descriptors) = model.infer_with_descriptors(&input).unwrap();
+
+        assert_eq!(heatmap.size(), vec![240, 320]);
+        assert!(heatmap.allclose(&model.infer(&input).unwrap(), 1e-5, 1e-5, false));
+        assert_eq!(descriptors.size(), vec![1, 256, 30, 40]);
+        assert_eq!(descriptors.kind(), Kind::Float);
+        assert_eq!(descriptors.device(), Device::Cpu);
+    }
+
+    #[test]
+    fn half_precision_is_rejected_on_cpu() {
+        let mut config = Config::default();
+        config.model.use_cuda = false;
+        config.model.precision = Precision::Float16;
+
+        match SuperPointModel::new(&config) {
+            Err(SuperPointError::ModelLoading(message)) => assert!(message.contains("Float16"), "{}", message),
+            Err(other) => panic!("expected a model loading error, got {}", other),
+            Ok(_) => panic!("Float16 on CPU should be rejected"),
+        }
+    }
+
+    #[test]
+    fn warmup_pads_inputs_off_the_cell_grid() {
+        let mut config = Config::default();
+        config.model.use_cuda = false;
+        (config.image.height, config.image.width) = (250, 330);
+        let model = SuperPointModel::new(&config).expect("superpoint_v2.pt should load on CPU");
+
+        model.warmup().unwrap();
+    }
+
+    #[test]
+    fn depth_to_space_places_channels_row_major_within_cells() {
+        // Channel 1 is in-cell row 0, column 1; channel 2 is row 1, column 0.
+        let cells = Tensor::zeros([4, 2, 3], (Kind::Float, Device::Cpu));
+        let _ = cells.get(1).get(1).get(2).fill_(1.0);
+        let _ = cells.get(2).get(0).get(0).fill_(2.0);
+
+        let heatmap = depth_to_space(&cells, 2).unwrap();
+
+        assert_eq!(heatmap.size(), vec![4, 6]);
+        assert_eq!(heatmap.double_value(&[2, 5]), 1.0);
+        assert_eq!(heatmap.double_value(&[1, 0]), 2.0);
+        assert_eq!(heatmap.sum(Kind::Float).double_value(&[]), 3.0);
+    }
+
+    #[test]
+    fn depth_to_space_rejects_mismatched_channel_count() {
+        let cells = Tensor::zeros([64, 2, 3], (Kind::Float, Device::Cpu));
+
+        assert!(matches!(depth_to_space(&cells, 4), Err(SuperPointError::Inference(_))));
+    }
+
+    #[test]
+    fn single_hot_cell_yields_keypoint_at_its_pixel() {
+        // Channel 42 is in-cell row 5, column 2; at coarse cell (row 1, col 3) that is pixel (x 26, y 13).
+        let model = cpu_model();
+        let semi = Tensor::zeros([65, 3, 4], (Kind::Float, Device::Cpu));
+        let _ = semi.get(42).get(1).get(3).fill_(20.0);
+        let heatmap = model.semi_to_heatmap(semi, (24, 32)).unwrap();
+
+        let mut keypoint_config = Config::default().keypoint;
+        keypoint_config.threshold = 0.5;
+        keypoint_config.border = None;
+        let keypoints = crate::postprocessing::KeypointExtractor::new(keypoint_config)
+            .extract_keypoints(&heatmap)
+            .unwrap();
+
+        assert_eq!(keypoints.len(), 1);
+        assert_eq!((keypoints[0].x, keypoints[0].y), (26.0, 13.0));
+    }
+}
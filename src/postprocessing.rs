@@ -1,74 +1,484 @@
-use crate::config::KeypointConfig;
+use crate::config::{KeypointConfig, KeypointSelectionPolicy, NmsMode, PlateauHandling, ScoreAggregation, ThresholdMode};
 use crate::error::SuperPointError;
-use crate::keypoint::Keypoint;
+use crate::geometry::{ModelDims, OriginalDims};
+use crate::keypoint::{AffineRegion, Keypoint};
+use crate::preprocessing::Letterbox;
+use image::GrayImage;
+use log::{debug, warn};
 use rayon::prelude::*;
-use tch::{Device, Tensor};
+use std::collections::{HashMap, HashSet, VecDeque};
+use tch::{Device, Kind, Tensor};
+
+const AFFINE_WINDOW_RADIUS: i64 = 6;
+const ORIENTATION_WINDOW_RADIUS: i64 = 8;
+const ORIENTATION_BINS: usize = 36;
+const CELL_SIZE: f32 = 8.0;
+/// Candidates considered per requested keypoint by `extract_top_k`, before NMS
+const TOP_K_OVERSAMPLE: usize = 4;
+/// Mask pixels at or above this gray level keep the keypoints on them
+const MASK_THRESHOLD: u8 = 128;
+/// Grid-aligned candidates scoring below this multiple of the threshold count as artifacts
+const GRID_ARTIFACT_SCORE_RATIO: f64 = 2.0;
 
 pub struct KeypointExtractor {
     config: KeypointConfig,
+    mask: Option<GrayImage>,
 }
 
 impl KeypointExtractor {
     pub fn new(config: KeypointConfig) -> Self {
-        Self { config }
+        Self { config, mask: None }
+    }
+    
+    /// Restricts detection to a model-space region of interest: keypoints landing on mask
+    /// pixels below 128 are discarded. A mask of a different size than the heatmap is
+    /// sampled proportionally, so one mask serves every pyramid level.
+    pub fn with_mask(mut self, mask: GrayImage) -> Self {
+        self.mask = Some(mask);
+        self
     }
 
     pub fn extract_keypoints(&self, heatmap: &Tensor) -> Result<Vec<Keypoint>, SuperPointError> {
+        if let Some(k) = self.config.top_k {
+            return self.extract_top_k(heatmap, k);
+        }
+        
+        let heatmap = &self.prepare_heatmap(heatmap);
+        
         // 1. Threshold-based filtering
-        let mut keypoints = self.extract_candidates(heatmap)?;
+        let keypoints = self.extract_candidates(heatmap, self.effective_threshold(heatmap)?)?;
+        
+        Ok(self.refine_candidates(keypoints, heatmap_size(heatmap)?))
+    }
+    
+    /// Ignores `threshold` and takes the `TOP_K_OVERSAMPLE * k` highest-scoring pixels as
+    /// candidates, runs the usual border/NMS/selection steps on them and keeps the best `k`.
+    /// Oversampling leaves NMS room to discard neighbours and still return about `k` keypoints.
+    pub fn extract_top_k(&self, heatmap: &Tensor, k: usize) -> Result<Vec<Keypoint>, SuperPointError> {
+        let heatmap = &self.prepare_heatmap(heatmap);
+        let (height, width) = heatmap_size(heatmap)?;
+        
+        let pool = (k.saturating_mul(TOP_K_OVERSAMPLE) as i64).min(height * width);
+        if pool == 0 {
+            return Ok(Vec::new());
+        }
+        
+        let (scores, indices) = heatmap.reshape([-1]).topk(pool, 0, true, true);
+        let scores: Vec<f32> = Vec::try_from(scores.to_device(Device::Cpu).to_kind(Kind::Float))
+            .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to extract top-k scores: {}", e)))?;
+        let indices: Vec<i64> = Vec::try_from(indices.to_device(Device::Cpu))
+            .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to extract top-k indices: {}", e)))?;
+        
+        let mut candidates = indices
+            .iter()
+            .zip(&scores)
+            .map(|(&index, &score)| Keypoint::new((index % width) as f32, (index / width) as f32, score))
+            .collect();
+        retain_finite(&mut candidates);
+        
+        let mut keypoints = self.refine_candidates(candidates, (height, width));
+        keypoints.sort_by(Keypoint::cmp_by_score);
+        keypoints.truncate(k);
+        
+        Ok(keypoints)
+    }
+    
+    /// Extracts keypoints from each heatmap of a `[B, H, W]` batch.
+    pub fn extract_batch(&self, heatmaps: &Tensor) -> Result<Vec<Vec<Keypoint>>, SuperPointError> {
+        if heatmaps.dim() != 3 {
+            return Err(SuperPointError::KeypointExtraction(format!(
+                "Expected batched heatmaps [B, H, W], got {:?}",
+                heatmaps.size()
+            )));
+        }
+        
+        (0..heatmaps.size()[0])
+            .map(|i| self.extract_keypoints(&heatmaps.get(i)))
+            .collect()
+    }
+    
+    /// Like `extract_batch`, flattened to `(batch_index, keypoint)` pairs for streaming.
+    pub fn extract_batch_flat(&self, heatmaps: &Tensor) -> Result<Vec<(usize, Keypoint)>, SuperPointError> {
+        Ok(self
+            .extract_batch(heatmaps)?
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, keypoints)| keypoints.into_iter().map(move |kp| (i, kp)))
+            .collect())
+    }
+    
+    /// Extracts candidates once at the lowest threshold and assigns every surviving keypoint
+    /// to the highest threshold it exceeds. Bands are returned from highest to lowest threshold;
+    /// thresholds are absolute regardless of `threshold_mode`.
+    pub fn extract_bands(
+        &self,
+        heatmap: &Tensor,
+        thresholds: &[f64],
+    ) -> Result<Vec<(f64, Vec<Keypoint>)>, SuperPointError> {
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_by(|a, b| b.total_cmp(a));
+        
+        let Some(&lowest) = thresholds.last() else {
+            return Ok(Vec::new());
+        };
+        
+        let heatmap = &self.prepare_heatmap(heatmap);
+        
+        let keypoints = self.refine_candidates(self.extract_candidates(heatmap, lowest)?, heatmap_size(heatmap)?);
+        
+        let mut bands: Vec<(f64, Vec<Keypoint>)> = thresholds.iter().map(|&t| (t, Vec::new())).collect();
+        for kp in keypoints {
+            if let Some(band) = bands.iter_mut().find(|(t, _)| kp.score as f64 > *t) {
+                band.1.push(kp);
+            }
+        }
+        
+        Ok(bands)
+    }
+    
+    /// Applies the optional local-contrast normalization: each pixel is divided by the mean
+    /// response in its window, then the map is rescaled so its maximum is 1. Uniform texture
+    /// ends up near `1 / max_ratio` while isolated peaks stay close to 1.
+    fn prepare_heatmap(&self, heatmap: &Tensor) -> Tensor {
+        let Some(window) = self.config.local_contrast_window else {
+            return heatmap.shallow_clone();
+        };
+        
+        // Force an odd window so it is centered on each pixel
+        let window = window.max(1) | 1;
+        let local_mean = heatmap
+            .unsqueeze(0)
+            .unsqueeze(0)
+            .avg_pool2d([window, window], [1, 1], [window / 2, window / 2], false, false, None::<i64>)
+            .squeeze_dim(0)
+            .squeeze_dim(0);
+        
+        let contrast = heatmap / (local_mean + 1e-6);
+        let max = contrast.max().clamp_min(1e-12);
+        contrast / max
+    }
+    
+    fn refine_candidates(&self, mut keypoints: Vec<Keypoint>, (height, width): (i64, i64)) -> Vec<Keypoint> {
+        // 1. Keep only keypoints inside the region-of-interest mask, if any
+        if let Some(mask) = &self.mask {
+            let scale_x = mask.width() as f32 / width as f32;
+            let scale_y = mask.height() as f32 / height as f32;
+            keypoints.retain(|kp| {
+                let (x, y) = ((kp.x * scale_x) as u32, (kp.y * scale_y) as u32);
+                x < mask.width() && y < mask.height() && mask.get_pixel(x, y).0[0] >= MASK_THRESHOLD
+            });
+        }
+        
+        // 1a. Drop polar keypoints of equirectangular frames if configured
+        if self.config.equirectangular
+            && let Some(cutoff) = self.config.pole_cutoff
+        {
+            keypoints.retain(|kp| {
+                let latitude = 90.0 - (kp.y + 0.5) / height as f32 * 180.0;
+                latitude.abs() <= cutoff
+            });
+        }
+        
+        // 1b. Handle saturated plateaus if configured
+        if let Some(max_plateau_size) = self.config.max_plateau_size {
+            keypoints = self.handle_plateaus(keypoints, max_plateau_size);
+        }
+        
+        // 1c. Drop keypoints too close to the model-space border if configured
+        if let Some(border) = self.config.border {
+            let border = border as f32;
+            keypoints.retain(|kp| {
+                kp.x >= border
+                    && kp.y >= border
+                    && kp.x < width as f32 - border
+                    && kp.y < height as f32 - border
+            });
+        }
         
         // 2. Apply Non-Maximum Suppression if configured
-        if let Some(nms_radius) = self.config.nms_radius {
-            keypoints = self.apply_nms(keypoints, nms_radius);
+        match self.config.nms_mode {
+            NmsMode::Radius => {
+                if let Some(nms_radius) = self.config.nms_radius {
+                    keypoints = if self.config.equirectangular {
+                        self.apply_nms_wrapped(keypoints, nms_radius, width as f32)
+                    } else {
+                        self.apply_nms(keypoints, nms_radius)
+                    };
+                }
+            }
+            NmsMode::Grid => {
+                keypoints = self.apply_grid_nms(
+                    keypoints,
+                    self.config.grid_nms_cell_size,
+                    self.config.grid_nms_max_per_cell,
+                );
+            }
+            NmsMode::None => {}
         }
         
         // 3. Limit number of keypoints if configured
         if let Some(max_kpts) = self.config.max_keypoints {
-            keypoints.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
-            keypoints.truncate(max_kpts);
+            keypoints = match self.config.selection_policy {
+                KeypointSelectionPolicy::TopScore => {
+                    keypoints.sort_by(Keypoint::cmp_by_score);
+                    keypoints.truncate(max_kpts);
+                    keypoints
+                }
+                KeypointSelectionPolicy::Coverage => self.select_with_coverage(keypoints, max_kpts),
+            };
+        }
+        
+        keypoints
+    }
+    
+    /// Spends the budget on the strongest keypoint of each non-empty grid cell first. When there
+    /// are more cells than budget, the strongest cells win; leftover budget goes to the
+    /// highest-scoring keypoints not yet selected. The result is sorted by descending score.
+    fn select_with_coverage(&self, mut keypoints: Vec<Keypoint>, budget: usize) -> Vec<Keypoint> {
+        keypoints.sort_by(Keypoint::cmp_by_score);
+        
+        let cell = self.config.coverage_cell_size.max(1) as f32;
+        let mut covered = HashSet::new();
+        let (mut selected, leftovers): (Vec<Keypoint>, Vec<Keypoint>) = keypoints
+            .into_iter()
+            .partition(|kp| covered.insert(((kp.x / cell) as i64, (kp.y / cell) as i64)));
+        
+        selected.truncate(budget);
+        let remaining = budget - selected.len();
+        selected.extend(leftovers.into_iter().take(remaining));
+        selected.sort_by(Keypoint::cmp_by_score);
+        selected
+    }
+    
+    fn extract_candidates(&self, heatmap: &Tensor, threshold: f64) -> Result<Vec<Keypoint>, SuperPointError> {
+        let mut keypoints = match self.config.block_rows {
+            // Threshold one row-block at a time to cap the size of the mask/nonzero temporaries
+            Some(block_rows) if block_rows > 0 => {
+                let height = heatmap.size()[0];
+                let mut keypoints = Vec::new();
+                for start in (0..height).step_by(block_rows as usize) {
+                    let rows = heatmap.narrow(0, start, block_rows.min(height - start));
+                    keypoints.extend(self.candidates_in_rows(&rows, threshold, start)?);
+                }
+                keypoints
+            }
+            _ => self.candidates_in_rows(heatmap, threshold, 0)?,
+        };
+        retain_finite(&mut keypoints);
+        
+        // Depth-to-space leaves a faint grid at cell corners; drop weak candidates sitting on it
+        if self.config.suppress_grid_artifacts {
+            let cell = CELL_SIZE as i64;
+            let marginal = threshold * GRID_ARTIFACT_SCORE_RATIO;
+            keypoints.retain(|kp| {
+                kp.x as i64 % cell != 0 || kp.y as i64 % cell != 0 || kp.score as f64 >= marginal
+            });
         }
         
         Ok(keypoints)
     }
     
-    fn extract_candidates(&self, heatmap: &Tensor) -> Result<Vec<Keypoint>, SuperPointError> {
+    /// Thresholds a `[rows, W]` slice of the heatmap whose first row is `row_offset`.
+    fn candidates_in_rows(&self, heatmap: &Tensor, threshold: f64, row_offset: i64) -> Result<Vec<Keypoint>, SuperPointError> {
         // Create threshold tensor on same device as heatmap
-        let threshold_tensor = Tensor::from(self.config.threshold).to_device(heatmap.device());
+        let threshold_tensor = Tensor::from(threshold).to_device(heatmap.device());
         
         // Boolean mask of pixels above threshold
         let mask = heatmap.gt_tensor(&threshold_tensor);
         
-        // Get coordinates of non-zero entries
+        // Gather everything on the heatmap's device: one index_select on the flattened heatmap
+        // pulls all N scores, so row i of the [N, 3] result describes the i-th nonzero pixel
         let nz_coords = mask.nonzero();
+        let row_idx = nz_coords.select(1, 0);
+        let col_idx = nz_coords.select(1, 1);
+        let flat_idx = &row_idx * heatmap.size()[1] + &col_idx;
+        let scores = heatmap.reshape([-1]).index_select(0, &flat_idx).to_kind(Kind::Float);
+        let rows = (row_idx + row_offset).to_kind(Kind::Float);
+        let cols = col_idx.to_kind(Kind::Float);
+        let candidates = Tensor::stack(&[cols, rows, scores], 1).to_device(Device::Cpu);
         
-        // Convert to CPU for processing
-        let coords_cpu = nz_coords.to_device(Device::Cpu);
-        let heatmap_cpu = heatmap.to_device(Device::Cpu);
+        // One contiguous transfer of (x, y, score) triples
+        let data: Vec<f32> = Vec::try_from(candidates.contiguous().view((-1,)))
+            .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to extract candidates: {}", e)))?;
         
-        // Extract coordinate pairs and scores
-        let coords_data: Vec<i64> = Vec::try_from(coords_cpu.contiguous().view((-1,)))
-            .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to extract coordinates: {}", e)))?;
+        Ok(data
+            .chunks_exact(3)
+            .map(|chunk| Keypoint::new(chunk[0], chunk[1], chunk[2]))
+            .collect())
+    }
+    
+    fn effective_threshold(&self, heatmap: &Tensor) -> Result<f64, SuperPointError> {
+        match self.config.threshold_mode {
+            ThresholdMode::Absolute => Ok(self.config.threshold),
+            ThresholdMode::Percentile => {
+                let quantile = heatmap
+                    .to_kind(Kind::Float)
+                    .f_quantile_scalar(self.config.threshold, None::<i64>, false, "linear")
+                    .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to compute heatmap percentile: {}", e)))?;
+                
+                f64::try_from(quantile)
+                    .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to read heatmap percentile: {}", e)))
+            }
+        }
+    }
+    
+    fn handle_plateaus(&self, keypoints: Vec<Keypoint>, max_size: usize) -> Vec<Keypoint> {
+        let index: HashMap<(i64, i64), usize> = keypoints
+            .iter()
+            .enumerate()
+            .map(|(i, kp)| ((kp.x as i64, kp.y as i64), i))
+            .collect();
         
-        let mut keypoints = Vec::with_capacity(coords_data.len() / 2);
+        let mut visited = vec![false; keypoints.len()];
+        let mut result = Vec::with_capacity(keypoints.len());
         
-        for chunk in coords_data.chunks_exact(2) {
-            let row = chunk[0];
-            let col = chunk[1];
+        for start in 0..keypoints.len() {
+            if visited[start] {
+                continue;
+            }
+            
+            // Flood-fill the 8-connected region of candidates sharing this score
+            let score = keypoints[start].score;
+            let mut plateau = vec![start];
+            let mut queue = VecDeque::from([start]);
+            visited[start] = true;
+            
+            while let Some(i) = queue.pop_front() {
+                let (x, y) = (keypoints[i].x as i64, keypoints[i].y as i64);
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        if let Some(&j) = index.get(&(x + dx, y + dy))
+                            && !visited[j]
+                            && (keypoints[j].score - score).abs() <= f32::EPSILON
+                        {
+                            visited[j] = true;
+                            plateau.push(j);
+                            queue.push_back(j);
+                        }
+                    }
+                }
+            }
             
-            // Get the score at this position
-            let score_tensor = heatmap_cpu.get(row).get(col);
-            let score: f32 = f32::try_from(score_tensor)
-                .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to extract score: {}", e)))?;
+            if plateau.len() <= max_size {
+                result.extend(plateau.iter().map(|&i| keypoints[i].clone()));
+                continue;
+            }
             
-            keypoints.push(Keypoint::new(col as f32, row as f32, score));
+            match self.config.plateau_handling {
+                PlateauHandling::Reject => {}
+                PlateauHandling::Collapse => {
+                    let n = plateau.len() as f32;
+                    let cx = plateau.iter().map(|&i| keypoints[i].x).sum::<f32>() / n;
+                    let cy = plateau.iter().map(|&i| keypoints[i].y).sum::<f32>() / n;
+                    result.push(Keypoint::new(cx, cy, score));
+                }
+            }
         }
         
-        Ok(keypoints)
+        result
     }
     
-    fn apply_nms(&self, mut keypoints: Vec<Keypoint>, radius: f32) -> Vec<Keypoint> {
+    pub fn apply_nms(&self, keypoints: Vec<Keypoint>, radius: f32) -> Vec<Keypoint> {
+        self.nms_with_distance(keypoints, radius, None, |a, b| a.distance_to(b), |_, _| {})
+    }
+    
+    /// Radius NMS that folds the scores of every suppressed keypoint into the survivor that
+    /// suppressed it, combined per `aggregation`. The result is re-sorted by descending score.
+    pub fn merge_overlapping(
+        &self,
+        keypoints: Vec<Keypoint>,
+        radius: f32,
+        aggregation: ScoreAggregation,
+    ) -> Vec<Keypoint> {
+        let mut absorbed: HashMap<usize, Vec<f32>> = HashMap::new();
+        let mut merged = self.nms_with_distance(
+            keypoints,
+            radius,
+            None,
+            |a, b| a.distance_to(b),
+            |survivor, kp| absorbed.entry(survivor).or_default().push(kp.score),
+        );
+        
+        for (survivor, scores) in absorbed {
+            let kp = &mut merged[survivor];
+            let sum = kp.score + scores.iter().sum::<f32>();
+            kp.score = match aggregation {
+                ScoreAggregation::Max => kp.score,
+                ScoreAggregation::Mean => sum / (scores.len() + 1) as f32,
+                ScoreAggregation::Sum => sum,
+            };
+        }
+        
+        merged.sort_by(Keypoint::cmp_by_score);
+        merged
+    }
+    
+    /// Keeps the `max_per_cell` highest-scoring keypoints of every `cell_size` x `cell_size`
+    /// grid cell. Linear in the number of keypoints and spreads them evenly over the image.
+    /// The result is sorted by descending score.
+    pub fn apply_grid_nms(&self, mut keypoints: Vec<Keypoint>, cell_size: u32, max_per_cell: usize) -> Vec<Keypoint> {
+        keypoints.sort_by(Keypoint::cmp_by_score);
+        
+        let cell = cell_size.max(1) as f32;
+        let mut counts: HashMap<(i64, i64), usize> = HashMap::new();
+        keypoints.retain(|kp| {
+            let count = counts.entry(((kp.x / cell) as i64, (kp.y / cell) as i64)).or_insert(0);
+            *count += 1;
+            *count <= max_per_cell
+        });
+        
+        keypoints
+    }
+    
+    /// Radius NMS where the left and right image edges are adjacent (360° panoramas).
+    pub fn apply_nms_wrapped(&self, keypoints: Vec<Keypoint>, radius: f32, width: f32) -> Vec<Keypoint> {
+        self.nms_with_distance(
+            keypoints,
+            radius,
+            Some(width),
+            |a, b| {
+                let dx = (a.x - b.x).abs();
+                let dx = dx.min(width - dx);
+                (dx.powi(2) + (a.y - b.y).powi(2)).sqrt()
+            },
+            |_, _| {},
+        )
+    }
+    
+    /// Greedy NMS in descending score order. Keypoints are binned into a spatial hash with
+    /// `radius`-sized cells, so each survivor only checks the 3x3 neighbouring buckets instead
+    /// of every remaining keypoint; `wrap_width` makes the outermost bucket columns adjacent
+    /// and widens the columns so they tile the width exactly.
+    /// `on_suppress` receives the result index of the survivor and each keypoint it suppresses.
+    fn nms_with_distance(
+        &self,
+        mut keypoints: Vec<Keypoint>,
+        radius: f32,
+        wrap_width: Option<f32>,
+        distance: impl Fn(&Keypoint, &Keypoint) -> f32,
+        mut on_suppress: impl FnMut(usize, &Keypoint),
+    ) -> Vec<Keypoint> {
         // Sort by score (descending)
-        keypoints.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        keypoints.sort_by(Keypoint::cmp_by_score);
+        
+        // Nothing is ever closer than a non-positive radius
+        if radius.is_nan() || radius <= 0.0 {
+            return keypoints;
+        }
+        
+        // When wrapping, split the width into whole columns at least `radius` wide, so the
+        // buckets either side of the seam are still within one column of each other
+        let columns = wrap_width.map(|width| ((width / radius).floor() as i64).max(1));
+        let column_width = match (wrap_width, columns) {
+            (Some(width), Some(columns)) => width / columns as f32,
+            _ => radius,
+        };
+        let bucket_of = |kp: &Keypoint| ((kp.x / column_width).floor() as i64, (kp.y / radius).floor() as i64);
+        
+        let mut buckets: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        for (i, kp) in keypoints.iter().enumerate() {
+            buckets.entry(bucket_of(kp)).or_default().push(i);
+        }
         
         let mut suppressed = vec![false; keypoints.len()];
         let mut result = Vec::new();
@@ -80,12 +490,25 @@ impl KeypointExtractor {
             
             result.push(keypoints[i].clone());
             
-            // Suppress nearby keypoints
-            for j in (i + 1)..keypoints.len() {
-                if !suppressed[j] {
-                    let distance = keypoints[i].distance_to(&keypoints[j]);
-                    if distance < radius {
-                        suppressed[j] = true;
+            let (bx, by) = bucket_of(&keypoints[i]);
+            let mut neighbour_columns = vec![bx - 1, bx, bx + 1];
+            if let Some(columns) = columns {
+                neighbour_columns.iter_mut().for_each(|c| *c = c.rem_euclid(columns));
+                neighbour_columns.sort_unstable();
+                neighbour_columns.dedup();
+            }
+            
+            // Suppress nearby, lower-ranked keypoints
+            for &cx in &neighbour_columns {
+                for cy in (by - 1)..=(by + 1) {
+                    let Some(members) = buckets.get(&(cx, cy)) else {
+                        continue;
+                    };
+                    for &j in members {
+                        if j > i && !suppressed[j] && distance(&keypoints[i], &keypoints[j]) < radius {
+                            suppressed[j] = true;
+                            on_suppress(result.len() - 1, &keypoints[j]);
+                        }
                     }
                 }
             }
@@ -94,15 +517,397 @@ impl KeypointExtractor {
         result
     }
     
+    /// Estimates a `[σxx, σxy, σyy]` covariance per keypoint from the inverse of the
+    /// negated heatmap Hessian at its (model-space) location. Entries are `None` for
+    /// keypoints on the heatmap border or where the surface is not a proper peak.
+    pub fn estimate_uncertainty(
+        &self,
+        keypoints: &[Keypoint],
+        heatmap: &Tensor,
+    ) -> Result<Vec<Option<[f32; 3]>>, SuperPointError> {
+        let dims = heatmap.size();
+        if dims.len() != 2 {
+            return Err(SuperPointError::KeypointExtraction(format!(
+                "Expected 2D heatmap, got {:?}",
+                dims
+            )));
+        }
+        let (height, width) = (dims[0], dims[1]);
+        
+        let data: Vec<f32> = Vec::try_from(heatmap.to_device(Device::Cpu).contiguous().view((-1,)))
+            .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to read heatmap: {}", e)))?;
+        let at = |x: i64, y: i64| data[(y * width + x) as usize];
+        
+        Ok(keypoints
+            .iter()
+            .map(|kp| {
+                let x = kp.x.round() as i64;
+                let y = kp.y.round() as i64;
+                if x < 1 || y < 1 || x >= width - 1 || y >= height - 1 {
+                    return None;
+                }
+                
+                // Finite-difference Hessian of the heatmap
+                let center = at(x, y);
+                let dxx = at(x + 1, y) - 2.0 * center + at(x - 1, y);
+                let dyy = at(x, y + 1) - 2.0 * center + at(x, y - 1);
+                let dxy = (at(x + 1, y + 1) - at(x + 1, y - 1) - at(x - 1, y + 1) + at(x - 1, y - 1)) / 4.0;
+                
+                // Invert -H, which is positive definite at a true maximum
+                let (a, b, c) = (-dxx, -dxy, -dyy);
+                let det = a * c - b * b;
+                if a <= 0.0 || det <= f32::EPSILON {
+                    return None;
+                }
+                
+                Some([c / det, -b / det, a / det])
+            })
+            .collect())
+    }
+    
+    /// Estimates an elliptical region per keypoint from the Gaussian-weighted second-moment
+    /// matrix of image gradients. `gray_tensor` is the `[H, W]` or `[1, 1, H, W]` model input
+    /// and keypoints are in the same (model) space. The ellipse keeps the area of a circle
+    /// of radius `AFFINE_WINDOW_RADIUS`; entries are `None` near the border or in flat regions.
+    pub fn estimate_affine_shape(
+        &self,
+        keypoints: &[Keypoint],
+        gray_tensor: &Tensor,
+    ) -> Result<Vec<Option<AffineRegion>>, SuperPointError> {
+        let dims = gray_tensor.size();
+        if dims.len() < 2 || dims[..dims.len() - 2].iter().any(|&d| d != 1) {
+            return Err(SuperPointError::KeypointExtraction(format!(
+                "Expected a single grayscale image tensor, got {:?}",
+                dims
+            )));
+        }
+        let (height, width) = (dims[dims.len() - 2], dims[dims.len() - 1]);
+        
+        let data: Vec<f32> = Vec::try_from(
+            gray_tensor.to_device(Device::Cpu).to_kind(Kind::Float).contiguous().view((-1,)),
+        )
+        .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to read image tensor: {}", e)))?;
+        let at = |x: i64, y: i64| data[(y * width + x) as usize];
+        
+        let radius = AFFINE_WINDOW_RADIUS;
+        let sigma = radius as f32 / 2.0;
+        
+        Ok(keypoints
+            .iter()
+            .map(|kp| {
+                let cx = kp.x.round() as i64;
+                let cy = kp.y.round() as i64;
+                
+                // Central differences need one extra pixel beyond the window
+                if cx - radius < 1 || cy - radius < 1 || cx + radius >= width - 1 || cy + radius >= height - 1 {
+                    return None;
+                }
+                
+                let (mut a, mut b, mut c) = (0.0f32, 0.0f32, 0.0f32);
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let (x, y) = (cx + dx, cy + dy);
+                        let ix = (at(x + 1, y) - at(x - 1, y)) / 2.0;
+                        let iy = (at(x, y + 1) - at(x, y - 1)) / 2.0;
+                        let weight = (-((dx * dx + dy * dy) as f32) / (2.0 * sigma * sigma)).exp();
+                        
+                        a += weight * ix * ix;
+                        b += weight * ix * iy;
+                        c += weight * iy * iy;
+                    }
+                }
+                
+                // Eigenvalues of [[a, b], [b, c]]
+                let half_trace = (a + c) / 2.0;
+                let spread = (((a - c) / 2.0).powi(2) + b * b).sqrt();
+                let lambda_max = half_trace + spread;
+                let lambda_min = half_trace - spread;
+                if lambda_min <= f32::EPSILON {
+                    return None;
+                }
+                
+                // The ellipse is elongated along the direction of least gradient energy
+                let anisotropy = (lambda_max / lambda_min).powf(0.25);
+                let angle = 0.5 * (2.0 * b).atan2(a - c) + std::f32::consts::FRAC_PI_2;
+                
+                Some(AffineRegion {
+                    x: kp.x,
+                    y: kp.y,
+                    major_axis: radius as f32 * anisotropy,
+                    minor_axis: radius as f32 / anisotropy,
+                    angle,
+                })
+            })
+            .collect())
+    }
+    
+    /// Assigns each keypoint its dominant gradient orientation (SIFT-style): gradient angles in
+    /// a window around the keypoint are accumulated into a magnitude- and Gaussian-weighted
+    /// histogram whose smoothed peak, refined by parabolic interpolation, becomes `kp.angle`
+    /// in radians. `image` must be in the keypoints' coordinate frame. Keypoints whose window
+    /// would leave the image, or whose patch is flat, get `angle = None`.
+    pub fn compute_orientations(&self, keypoints: &mut [Keypoint], image: &GrayImage) {
+        let (width, height) = (image.width() as i64, image.height() as i64);
+        let at = |x: i64, y: i64| image.get_pixel(x as u32, y as u32)[0] as f32;
+        
+        let radius = ORIENTATION_WINDOW_RADIUS;
+        let sigma = radius as f32 / 2.0;
+        let bin_width = std::f32::consts::TAU / ORIENTATION_BINS as f32;
+        
+        for kp in keypoints.iter_mut() {
+            let cx = kp.x.round() as i64;
+            let cy = kp.y.round() as i64;
+            
+            // Central differences need one extra pixel beyond the window
+            if cx - radius < 1 || cy - radius < 1 || cx + radius >= width - 1 || cy + radius >= height - 1 {
+                kp.angle = None;
+                continue;
+            }
+            
+            let mut histogram = [0.0f32; ORIENTATION_BINS];
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let (x, y) = (cx + dx, cy + dy);
+                    let gx = (at(x + 1, y) - at(x - 1, y)) / 2.0;
+                    let gy = (at(x, y + 1) - at(x, y - 1)) / 2.0;
+                    let weight = (-((dx * dx + dy * dy) as f32) / (2.0 * sigma * sigma)).exp();
+                    
+                    let angle = gy.atan2(gx).rem_euclid(std::f32::consts::TAU);
+                    let bin = ((angle / bin_width) as usize).min(ORIENTATION_BINS - 1);
+                    histogram[bin] += weight * (gx * gx + gy * gy).sqrt();
+                }
+            }
+            
+            // Circular [1, 1, 1] / 3 smoothing
+            let smoothed: Vec<f32> = (0..ORIENTATION_BINS)
+                .map(|i| {
+                    let prev = histogram[(i + ORIENTATION_BINS - 1) % ORIENTATION_BINS];
+                    let next = histogram[(i + 1) % ORIENTATION_BINS];
+                    (prev + histogram[i] + next) / 3.0
+                })
+                .collect();
+            
+            let (peak, &peak_value) = smoothed
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap();
+            if peak_value <= f32::EPSILON {
+                kp.angle = None;
+                continue;
+            }
+            
+            let prev = smoothed[(peak + ORIENTATION_BINS - 1) % ORIENTATION_BINS];
+            let next = smoothed[(peak + 1) % ORIENTATION_BINS];
+            let denominator = prev - 2.0 * peak_value + next;
+            let offset = if denominator.abs() > f32::EPSILON {
+                0.5 * (prev - next) / denominator
+            } else {
+                0.0
+            };
+            
+            kp.angle = Some(((peak as f32 + 0.5 + offset) * bin_width).rem_euclid(std::f32::consts::TAU));
+        }
+    }
+    
+    /// Bilinearly samples the coarse `[1, D, Hc, Wc]` (or `[D, Hc, Wc]`) descriptor map at each
+    /// model-space keypoint and returns an `[N, D]` tensor of L2-normalized descriptors.
+    /// The map may cover a model input padded up to whole cells.
+    pub fn sample_descriptors(
+        &self,
+        descriptors: &Tensor,
+        keypoints: &[Keypoint],
+        model_size: ModelDims,
+    ) -> Result<Tensor, SuperPointError> {
+        let sampled = self.sample_unnormalized(descriptors, keypoints, model_size)?;
+        let norms = sampled.norm_scalaropt_dim(2.0, [1], true).clamp_min(1e-12);
+        Ok(sampled / norms)
+    }
+    
+    /// `sample_descriptors` that also applies `min_descriptor_norm`: keypoints whose descriptor
+    /// norm before normalization is below it are dropped (near-zero descriptors from flat
+    /// regions only amplify noise when normalized). Returns the kept keypoints and their
+    /// `[N_kept, D]` descriptors in the same order.
+    pub fn sample_descriptors_filtered(
+        &self,
+        descriptors: &Tensor,
+        keypoints: Vec<Keypoint>,
+        model_size: ModelDims,
+    ) -> Result<(Vec<Keypoint>, Tensor), SuperPointError> {
+        let sampled = self.sample_unnormalized(descriptors, &keypoints, model_size)?;
+        let norms = sampled.norm_scalaropt_dim(2.0, [1], true);
+        
+        let Some(min_norm) = self.config.min_descriptor_norm else {
+            return Ok((keypoints, sampled / norms.clamp_min(1e-12)));
+        };
+        
+        let keep = norms.view((-1,)).ge(min_norm as f64);
+        let keep_flags: Vec<bool> = Vec::try_from(keep.to_device(Device::Cpu))
+            .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to read descriptor norms: {}", e)))?;
+        let index = keep.nonzero().view((-1,));
+        let kept_descriptors = sampled.index_select(0, &index) / norms.index_select(0, &index).clamp_min(1e-12);
+        
+        let before = keypoints.len();
+        let kept: Vec<Keypoint> = keypoints
+            .into_iter()
+            .zip(keep_flags)
+            .filter_map(|(kp, keep)| keep.then_some(kp))
+            .collect();
+        if kept.len() < before {
+            debug!("Dropped {} keypoints with descriptor norm below {}", before - kept.len(), min_norm);
+        }
+        
+        Ok((kept, kept_descriptors))
+    }
+    
+    /// Bilinear descriptor samples at `keypoints` as `[N, D]`, before L2 normalization.
+    fn sample_unnormalized(
+        &self,
+        descriptors: &Tensor,
+        keypoints: &[Keypoint],
+        model_size: ModelDims,
+    ) -> Result<Tensor, SuperPointError> {
+        let descriptors = match descriptors.dim() {
+            4 if descriptors.size()[0] == 1 => descriptors.shallow_clone(),
+            3 => descriptors.unsqueeze(0),
+            _ => {
+                return Err(SuperPointError::KeypointExtraction(format!(
+                    "Expected descriptor map [1, D, Hc, Wc] or [D, Hc, Wc], got {:?}",
+                    descriptors.size()
+                )));
+            }
+        };
+        let dims = descriptors.size();
+        let (depth, coarse_height, coarse_width) = (dims[1], dims[2], dims[3]);
+        let options = (descriptors.kind(), descriptors.device());
+        
+        // grid_sampler and the [D, N] view below assume a dense NCHW layout; some exports
+        // return the descriptor head as a permuted view
+        let descriptors = if descriptors.is_contiguous() {
+            descriptors
+        } else {
+            debug!("Descriptor map {:?} is not contiguous; copying before sampling", dims);
+            descriptors.contiguous()
+        };
+        
+        if keypoints.is_empty() {
+            return Ok(Tensor::zeros([0, depth], options));
+        }
+        
+        let cells = |len: i64| (len as f32 / CELL_SIZE).ceil() as i64;
+        if coarse_height != cells(model_size.height) || coarse_width != cells(model_size.width) {
+            return Err(SuperPointError::KeypointExtraction(format!(
+                "Descriptor map {}x{} does not match model size {}x{} at cell size {}",
+                coarse_height, coarse_width, model_size.height, model_size.width, CELL_SIZE
+            )));
+        }
+        
+        // Pixel coordinates -> [-1, 1] grid coordinates with align_corners=true. Each
+        // descriptor sits at the center of its 8x8 cell, i.e. pixel (c * 8 + 3.5).
+        let offset = CELL_SIZE / 2.0 - 0.5;
+        let span_x = (coarse_width - 1) as f32 * CELL_SIZE;
+        let span_y = (coarse_height - 1) as f32 * CELL_SIZE;
+        let grid_points: Vec<f32> = keypoints
+            .iter()
+            .flat_map(|kp| {
+                [
+                    (kp.x - offset) / span_x * 2.0 - 1.0,
+                    (kp.y - offset) / span_y * 2.0 - 1.0,
+                ]
+            })
+            .collect();
+        let grid = Tensor::from_slice(&grid_points)
+            .view((1, 1, keypoints.len() as i64, 2))
+            .to_kind(options.0)
+            .to_device(options.1);
+        
+        // Bilinear (0), zero padding (0), align_corners -> [1, D, 1, N]
+        Ok(descriptors
+            .f_grid_sampler(&grid, 0, 0, true)
+            .map_err(|e| SuperPointError::KeypointExtraction(format!("Descriptor sampling failed: {}", e)))?
+            .view((depth, keypoints.len() as i64))
+            .transpose(0, 1))
+    }
+    
+    /// Samples descriptors like `sample_descriptors_filtered` and stores each one in its
+    /// keypoint's `descriptor` field, so they travel with the keypoint through scaling, merging
+    /// and export. Keypoints rejected by `min_descriptor_norm` are dropped.
+    pub fn attach_descriptors(
+        &self,
+        descriptors: &Tensor,
+        keypoints: Vec<Keypoint>,
+        model_size: ModelDims,
+    ) -> Result<Vec<Keypoint>, SuperPointError> {
+        let (mut keypoints, sampled) = self.sample_descriptors_filtered(descriptors, keypoints, model_size)?;
+        let rows: Vec<Vec<f32>> = Vec::try_from(sampled.to_kind(Kind::Float).to_device(Device::Cpu))
+            .map_err(|e| SuperPointError::KeypointExtraction(format!("Failed to read descriptors: {}", e)))?;
+        
+        for (kp, row) in keypoints.iter_mut().zip(rows) {
+            kp.descriptor = Some(row);
+        }
+        Ok(keypoints)
+    }
+    
+    /// Shifts per-tile keypoints by their tile's `(x, y)` origin into full-image coordinates and
+    /// runs radius NMS over the union, which removes the duplicates detected twice in overlap
+    /// bands. `max_keypoints` is re-applied to the merged set.
+    pub fn merge_tiled_keypoints(&self, tiles: Vec<((u32, u32), Vec<Keypoint>)>, radius: f32) -> Vec<Keypoint> {
+        let keypoints: Vec<Keypoint> = tiles
+            .into_iter()
+            .flat_map(|((x0, y0), keypoints)| {
+                keypoints.into_iter().map(move |mut kp| {
+                    kp.x += x0 as f32;
+                    kp.y += y0 as f32;
+                    kp
+                })
+            })
+            .collect();
+        
+        let mut merged = self.apply_nms(keypoints, radius);
+        if let Some(max_kpts) = self.config.max_keypoints {
+            merged.truncate(max_kpts);
+        }
+        merged
+    }
+    
+    /// Drops original-space keypoints inside any of the configured `exclude_regions`.
+    pub fn apply_exclusions(&self, mut keypoints: Vec<Keypoint>) -> Vec<Keypoint> {
+        if self.config.exclude_regions.is_empty() {
+            return keypoints;
+        }
+        
+        keypoints.retain(|kp| {
+            !self.config.exclude_regions.iter().any(|&[x, y, width, height]| {
+                kp.x >= x as f32
+                    && kp.y >= y as f32
+                    && kp.x < x as f32 + width as f32
+                    && kp.y < y as f32 + height as f32
+            })
+        });
+        keypoints
+    }
+    
+    /// Maps model-space keypoints to the original image. Without a `letterbox` the input is
+    /// assumed stretched to `model_size`; with one, its offset is removed before undoing its
+    /// uniform scale, and keypoints that land in the padding are dropped.
     pub fn scale_keypoints_to_original(
         &self,
         keypoints: Vec<Keypoint>,
-        original_size: (u32, u32),
-        model_size: (i64, i64),
+        original_size: OriginalDims,
+        model_size: ModelDims,
+        letterbox: Option<Letterbox>,
     ) -> Vec<Keypoint> {
-        let scale_x = original_size.0 as f32 / model_size.1 as f32;
-        let scale_y = original_size.1 as f32 / model_size.0 as f32;
+        if let Some(letterbox) = letterbox {
+            return unletterbox(
+                keypoints,
+                letterbox.scale,
+                letterbox.offset_x as f32,
+                letterbox.offset_y as f32,
+                original_size,
+            );
+        }
         
+        let (scale_x, scale_y) = compute_scale_factors(original_size, model_size);
         keypoints
             .into_par_iter()
             .map(|mut kp| {
@@ -112,4 +917,652 @@ impl KeypointExtractor {
             })
             .collect()
     }
-}
\ No newline at end of file
+}
+
+/// Inverts a letterbox: removes the `(pad_x, pad_y)` offset first, then undoes the uniform
+/// `scale`, mapping model-space keypoints into an `original_size` image. Used for whole images
+/// and for each tile alike.
+///
+/// The image occupies `[0, width) x [0, height)` after the transform. A keypoint exactly on the
+/// near pad boundary (`x == pad_x`) lands on column 0 and is kept; one exactly on the far
+/// boundary lands on `x == width`, outside the image, and is dropped like every keypoint
+/// inside the padding.
+pub fn unletterbox(
+    keypoints: Vec<Keypoint>,
+    scale: f32,
+    pad_x: f32,
+    pad_y: f32,
+    original_size: OriginalDims,
+) -> Vec<Keypoint> {
+    let (width, height) = (original_size.width as f32, original_size.height as f32);
+    
+    keypoints
+        .into_par_iter()
+        .filter_map(|mut kp| {
+            kp.x = (kp.x - pad_x) / scale;
+            kp.y = (kp.y - pad_y) / scale;
+            (kp.x >= 0.0 && kp.y >= 0.0 && kp.x < width && kp.y < height).then_some(kp)
+        })
+        .collect()
+}
+
+/// Drops keypoints with NaN or infinite scores (e.g. from a broken model export), logging how many.
+fn retain_finite(keypoints: &mut Vec<Keypoint>) {
+    let before = keypoints.len();
+    keypoints.retain(|kp| kp.score.is_finite());
+    
+    let dropped = before - keypoints.len();
+    if dropped > 0 {
+        warn!("Dropped {} keypoint candidates with non-finite scores", dropped);
+    }
+}
+
+fn heatmap_size(heatmap: &Tensor) -> Result<(i64, i64), SuperPointError> {
+    match heatmap.size()[..] {
+        [height, width] => Ok((height, width)),
+        ref dims => Err(SuperPointError::KeypointExtraction(format!(
+            "Expected 2D heatmap, got {:?}",
+            dims
+        ))),
+    }
+}
+
+/// Returns the `(scale_x, scale_y)` mapping model-space pixels to the original image.
+pub fn compute_scale_factors(original_size: OriginalDims, model_size: ModelDims) -> (f32, f32) {
+    let scale_x = original_size.width as f32 / model_size.width as f32;
+    let scale_y = original_size.height as f32 / model_size.height as f32;
+    (scale_x, scale_y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn extractor(configure: impl FnOnce(&mut KeypointConfig)) -> KeypointExtractor {
+        let mut config = Config::default().keypoint;
+        configure(&mut config);
+        KeypointExtractor::new(config)
+    }
+
+    /// A saturated 5x5 plateau at (10..15, 20..25) plus one isolated peak.
+    fn plateau_candidates() -> Vec<Keypoint> {
+        let mut keypoints: Vec<Keypoint> = (20..25)
+            .flat_map(|y| (10..15).map(move |x| Keypoint::new(x as f32, y as f32, 1.0)))
+            .collect();
+        keypoints.push(Keypoint::new(50.0, 50.0, 0.8));
+        keypoints
+    }
+
+    #[test]
+    fn saturated_plateau_is_rejected() {
+        let extractor = extractor(|config| config.plateau_handling = PlateauHandling::Reject);
+        
+        let kept = extractor.handle_plateaus(plateau_candidates(), 4);
+        
+        assert_eq!(kept, vec![Keypoint::new(50.0, 50.0, 0.8)]);
+    }
+
+    #[test]
+    fn saturated_plateau_collapses_to_centroid() {
+        let extractor = extractor(|config| config.plateau_handling = PlateauHandling::Collapse);
+        
+        let mut kept = extractor.handle_plateaus(plateau_candidates(), 4);
+        kept.sort_by(Keypoint::cmp_by_score);
+        
+        assert_eq!(kept, vec![Keypoint::new(12.0, 22.0, 1.0), Keypoint::new(50.0, 50.0, 0.8)]);
+    }
+
+    #[test]
+    fn sampling_handles_non_contiguous_descriptor_map() {
+        let extractor = extractor(|_| {});
+        
+        // [1, D=2, 2, 2]; transposing H and W gives a non-contiguous view whose cell (row, col)
+        // holds the base tensor's cell (col, row)
+        let base = Tensor::from_slice(&[1.0f32, 0.0, 3.0, -1.0, 0.0, 1.0, 4.0, 0.0]).view((1, 2, 2, 2));
+        let transposed = base.transpose(2, 3);
+        assert!(!transposed.is_contiguous());
+        
+        // Descriptors sit at cell centers, pixel (c * 8 + 3.5)
+        let keypoints = [
+            Keypoint::new(3.5, 3.5, 1.0),
+            Keypoint::new(11.5, 3.5, 1.0),
+            Keypoint::new(3.5, 11.5, 1.0),
+        ];
+        let sampled = extractor
+            .sample_descriptors(&transposed, &keypoints, ModelDims::new(16, 16))
+            .unwrap();
+        let rows: Vec<Vec<f32>> = Vec::try_from(sampled).unwrap();
+        
+        let expected = [[1.0, 0.0], [0.6, 0.8], [0.0, 1.0]];
+        for (row, expected) in rows.iter().zip(expected) {
+            assert!(row.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-5), "{:?} != {:?}", row, expected);
+        }
+    }
+
+    #[test]
+    fn scale_factors_match_non_square_scaling() {
+        let extractor = extractor(|_| {});
+        let original = OriginalDims::new(640, 360);
+        let model = ModelDims::new(320, 240);
+        
+        let (scale_x, scale_y) = compute_scale_factors(original, model);
+        let scaled = extractor.scale_keypoints_to_original(vec![Keypoint::new(100.0, 60.0, 1.0)], original, model, None);
+        
+        assert_eq!((scale_x, scale_y), (2.0, 1.5));
+        assert_eq!((scaled[0].x, scaled[0].y), (100.0 * scale_x, 60.0 * scale_y));
+    }
+
+    /// A `size` x `size` heatmap holding one Gaussian peak of width `sigma` at its center.
+    fn gaussian_heatmap(size: i64, sigma: f32) -> Tensor {
+        let center = (size / 2) as f32;
+        let values: Vec<f32> = (0..size * size)
+            .map(|i| {
+                let (x, y) = ((i % size) as f32, (i / size) as f32);
+                (-((x - center).powi(2) + (y - center).powi(2)) / (2.0 * sigma * sigma)).exp()
+            })
+            .collect();
+        Tensor::from_slice(&values).view((size, size))
+    }
+    
+    #[test]
+    fn sharp_peak_is_more_certain_than_broad_peak() {
+        let extractor = extractor(|_| {});
+        let peak = [Keypoint::new(10.0, 10.0, 1.0)];
+        
+        let sharp = extractor.estimate_uncertainty(&peak, &gaussian_heatmap(21, 1.0)).unwrap()[0].unwrap();
+        let broad = extractor.estimate_uncertainty(&peak, &gaussian_heatmap(21, 4.0)).unwrap()[0].unwrap();
+        
+        assert!(sharp[0] < broad[0] && sharp[2] < broad[2], "sharp {:?} vs broad {:?}", sharp, broad);
+        
+        // Border keypoints are skipped
+        let border = extractor.estimate_uncertainty(&[Keypoint::new(0.0, 10.0, 1.0)], &gaussian_heatmap(21, 1.0));
+        assert_eq!(border.unwrap(), vec![None]);
+    }
+
+    #[test]
+    fn percentile_threshold_is_contrast_invariant() {
+        tch::manual_seed(0);
+        let bright = Tensor::rand([64, 64], (Kind::Float, Device::Cpu));
+        let dim = &bright * 0.1;
+        let count = |mode: ThresholdMode, threshold: f64, heatmap: &Tensor| {
+            let extractor = extractor(|config| {
+                config.threshold_mode = mode;
+                config.threshold = threshold;
+                config.nms_mode = NmsMode::None;
+                config.max_keypoints = None;
+            });
+            extractor.extract_keypoints(heatmap).unwrap().len()
+        };
+        
+        // An absolute threshold finds half the bright map and nothing in the dim one
+        assert!(count(ThresholdMode::Absolute, 0.5, &bright) > 1000);
+        assert_eq!(count(ThresholdMode::Absolute, 0.5, &dim), 0);
+        
+        let (bright_count, dim_count) = (
+            count(ThresholdMode::Percentile, 0.99, &bright),
+            count(ThresholdMode::Percentile, 0.99, &dim),
+        );
+        assert!(bright_count > 0);
+        assert!(bright_count.abs_diff(dim_count) <= 2, "{} vs {}", bright_count, dim_count);
+    }
+
+    #[test]
+    fn low_norm_descriptors_are_dropped_in_sync() {
+        let filtering = extractor(|config| config.min_descriptor_norm = Some(0.1));
+        
+        // [1, D=2, 2, 2] with an all-zero cell at row 0, column 1
+        let map = Tensor::from_slice(&[2.0f32, 0.0, 0.0, 3.0, 0.0, 0.0, 4.0, 4.0]).view((1, 2, 2, 2));
+        let keypoints = vec![
+            Keypoint::new(3.5, 3.5, 0.9),
+            Keypoint::new(11.5, 3.5, 0.8),
+            Keypoint::new(3.5, 11.5, 0.7),
+        ];
+        
+        let (kept, descriptors) = filtering
+            .sample_descriptors_filtered(&map, keypoints.clone(), ModelDims::new(16, 16))
+            .unwrap();
+        let rows: Vec<Vec<f32>> = Vec::try_from(descriptors).unwrap();
+        
+        assert_eq!(kept, vec![keypoints[0].clone(), keypoints[2].clone()]);
+        assert_eq!(rows.len(), 2);
+        assert!((rows[0][0] - 1.0).abs() < 1e-5 && rows[0][1].abs() < 1e-5);
+        assert!(rows[1][0].abs() < 1e-5 && (rows[1][1] - 1.0).abs() < 1e-5);
+        
+        // Without the option every keypoint keeps a (normalized) descriptor
+        let (all, _) = extractor(|_| {})
+            .sample_descriptors_filtered(&map, keypoints, ModelDims::new(16, 16))
+            .unwrap();
+        assert_eq!(all.len(), 3);
+    }
+
+    #[test]
+    fn unletterbox_handles_pad_boundaries() {
+        // 640x360 letterboxed into 320x240: scale 0.5, 30 px bars above and below
+        let original = OriginalDims::new(640, 360);
+        let letterbox = Letterbox::fit(original, ModelDims::new(320, 240));
+        assert_eq!(letterbox, Letterbox { scale: 0.5, offset_x: 0, offset_y: 30 });
+        
+        let keypoints = vec![
+            Keypoint::new(0.0, 30.0, 0.9),    // near pad boundary -> image origin
+            Keypoint::new(100.0, 29.5, 0.8),  // inside the top bar
+            Keypoint::new(160.0, 210.0, 0.7), // far pad boundary -> y == 360
+            Keypoint::new(319.5, 209.5, 0.6), // last image pixel
+        ];
+        
+        let mapped = unletterbox(keypoints.clone(), 0.5, 0.0, 30.0, original);
+        assert_eq!(mapped, vec![Keypoint::new(0.0, 0.0, 0.9), Keypoint::new(639.0, 359.0, 0.6)]);
+        
+        // The letterbox path of the scaler goes through the same transform
+        let scaled = extractor(|_| {}).scale_keypoints_to_original(keypoints, original, ModelDims::new(320, 240), Some(letterbox));
+        assert_eq!(scaled, mapped);
+    }
+
+    /// A `size` x `size` image holding a centered Gaussian blob with per-axis widths.
+    fn anisotropic_blob(size: i64, sigma_x: f32, sigma_y: f32) -> Tensor {
+        let center = (size / 2) as f32;
+        let values: Vec<f32> = (0..size * size)
+            .map(|i| {
+                let (dx, dy) = ((i % size) as f32 - center, (i / size) as f32 - center);
+                (-(dx * dx / (2.0 * sigma_x * sigma_x) + dy * dy / (2.0 * sigma_y * sigma_y))).exp()
+            })
+            .collect();
+        Tensor::from_slice(&values).view((size, size))
+    }
+    
+    #[test]
+    fn affine_region_is_elongated_along_blurred_axis() {
+        let extractor = extractor(|_| {});
+        let center = [Keypoint::new(20.0, 20.0, 1.0)];
+        
+        let wide = extractor.estimate_affine_shape(&center, &anisotropic_blob(41, 6.0, 2.0)).unwrap()[0].unwrap();
+        assert!(wide.major_axis > wide.minor_axis);
+        assert!(wide.angle.sin().abs() < 0.1, "major axis should be horizontal, angle {}", wide.angle);
+        
+        let tall = extractor.estimate_affine_shape(&center, &anisotropic_blob(41, 2.0, 6.0)).unwrap()[0].unwrap();
+        assert!(tall.major_axis > tall.minor_axis);
+        assert!(tall.angle.cos().abs() < 0.1, "major axis should be vertical, angle {}", tall.angle);
+    }
+
+    #[test]
+    fn merged_scores_follow_aggregation_mode() {
+        let extractor = extractor(|_| {});
+        
+        for (aggregation, expected) in [
+            (ScoreAggregation::Max, 0.6),
+            (ScoreAggregation::Mean, 0.5),
+            (ScoreAggregation::Sum, 1.0),
+        ] {
+            let keypoints = vec![Keypoint::new(10.0, 10.0, 0.4), Keypoint::new(11.0, 10.0, 0.6)];
+            let merged = extractor.merge_overlapping(keypoints, 4.0, aggregation);
+            
+            assert_eq!(merged.len(), 1);
+            assert_eq!(merged[0].x, 11.0);
+            assert!((merged[0].score - expected).abs() < 1e-6, "{:?}: {}", aggregation, merged[0].score);
+        }
+    }
+
+    #[test]
+    fn bands_take_the_highest_passing_threshold() {
+        let extractor = extractor(|_| {});
+        let mut values = vec![0.0f32; 32 * 32];
+        for (x, y, score) in [(8, 8, 0.9f32), (24, 8, 0.5), (16, 24, 0.2), (24, 24, 0.05)] {
+            values[y * 32 + x] = score;
+        }
+        let heatmap = Tensor::from_slice(&values).view((32, 32));
+        
+        let bands = extractor.extract_bands(&heatmap, &[0.1, 0.7, 0.4]).unwrap();
+        
+        let positions: Vec<(f64, Vec<(f32, f32)>)> = bands
+            .into_iter()
+            .map(|(t, kps)| (t, kps.iter().map(|kp| (kp.x, kp.y)).collect()))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![(0.7, vec![(8.0, 8.0)]), (0.4, vec![(24.0, 8.0)]), (0.1, vec![(16.0, 24.0)])]
+        );
+    }
+
+    #[test]
+    fn flat_batch_tags_keypoints_with_their_image() {
+        let extractor = extractor(|_| {});
+        let mut values = vec![0.0f32; 2 * 16 * 16];
+        values[4 * 16 + 4] = 0.9;
+        values[16 * 16 + 10 * 16 + 12] = 0.8;
+        values[16 * 16 + 3 * 16 + 2] = 0.7;
+        let heatmaps = Tensor::from_slice(&values).view((2, 16, 16));
+        
+        let flat = extractor.extract_batch_flat(&heatmaps).unwrap();
+        
+        let tagged: Vec<(usize, f32, f32)> = flat.iter().map(|(i, kp)| (*i, kp.x, kp.y)).collect();
+        assert_eq!(tagged, vec![(0, 4.0, 4.0), (1, 12.0, 10.0), (1, 2.0, 3.0)]);
+    }
+
+    #[test]
+    fn wrapped_nms_suppresses_across_the_seam() {
+        let extractor = extractor(|_| {});
+        let seam_pair = || vec![Keypoint::new(0.5, 60.0, 0.9), Keypoint::new(318.5, 60.0, 0.8)];
+        
+        assert_eq!(extractor.apply_nms(seam_pair(), 4.0).len(), 2);
+        
+        let kept = extractor.apply_nms_wrapped(seam_pair(), 4.0, 320.0);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].x, 0.5);
+    }
+    
+    #[test]
+    fn wrapped_nms_finds_seam_neighbours_when_width_is_not_a_radius_multiple() {
+        let extractor = extractor(|_| {});
+        // 2.1 px apart across the seam of a 13 px wide panorama
+        let seam_pair = vec![Keypoint::new(11.9, 5.0, 0.9), Keypoint::new(1.0, 5.0, 0.8)];
+        
+        let kept = extractor.apply_nms_wrapped(seam_pair, 4.0, 13.0);
+        
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].x, 11.9);
+    }
+
+    #[test]
+    fn local_contrast_suppresses_texture_flood() {
+        // Texture peaks every 4 px everywhere except a quiet patch holding one strong peak
+        let values: Vec<f32> = (0..64 * 64)
+            .map(|i| {
+                let (x, y) = (i % 64, i / 64);
+                if (40..56).contains(&x) && (40..56).contains(&y) {
+                    if (x, y) == (48, 48) { 0.9 } else { 0.0 }
+                } else if x % 4 == 0 && y % 4 == 0 {
+                    0.3
+                } else {
+                    0.1
+                }
+            })
+            .collect();
+        let heatmap = Tensor::from_slice(&values).view((64, 64));
+        
+        let flooded = extractor(|_| {}).extract_keypoints(&heatmap).unwrap();
+        let normalized = extractor(|config| config.local_contrast_window = Some(9))
+            .extract_keypoints(&heatmap)
+            .unwrap();
+        
+        assert!(flooded.len() > 100, "{} keypoints without normalization", flooded.len());
+        assert!(normalized.len() * 10 < flooded.len(), "{} keypoints survived normalization", normalized.len());
+        assert!(normalized.iter().any(|kp| (kp.x, kp.y) == (48.0, 48.0)));
+    }
+
+    #[test]
+    fn descriptors_are_sampled_at_cell_centers_and_normalized() {
+        let extractor = extractor(|_| {});
+        let model_size = ModelDims { width: 16, height: 16 };
+        // Two channels over 2x2 cells: cell (0, 0) = [3, 4], cell (1, 1) = [0, 2]
+        let map = Tensor::from_slice(&[3.0f32, 0.0, 0.0, 0.0, 4.0, 0.0, 0.0, 2.0]).view((1, 2, 2, 2));
+        let keypoints = [Keypoint::new(3.5, 3.5, 1.0), Keypoint::new(11.5, 11.5, 1.0)];
+        
+        let sampled = extractor.sample_descriptors(&map, &keypoints, model_size).unwrap();
+        
+        let expected = Tensor::from_slice(&[0.6f32, 0.8, 0.0, 1.0]).view((2, 2));
+        assert!(sampled.allclose(&expected, 1e-5, 1e-5, false), "{:?}", sampled);
+        
+        let empty = extractor.sample_descriptors(&map, &[], model_size).unwrap();
+        assert_eq!(empty.size(), vec![0, 2]);
+    }
+
+    #[test]
+    fn coverage_policy_fills_cells_before_leftovers() {
+        let extractor = extractor(|config| config.coverage_cell_size = 10);
+        let keypoints = || {
+            vec![
+                Keypoint::new(1.0, 1.0, 0.9),
+                Keypoint::new(2.0, 2.0, 0.85),
+                Keypoint::new(11.0, 1.0, 0.5),
+                Keypoint::new(12.0, 2.0, 0.45),
+                Keypoint::new(1.0, 11.0, 0.4),
+                Keypoint::new(11.0, 11.0, 0.3),
+            ]
+        };
+        let scores = |selected: Vec<Keypoint>| selected.iter().map(|kp| kp.score).collect::<Vec<_>>();
+        
+        // Budget below the four occupied cells: one per cell, the 0.85 runner-up loses out
+        assert_eq!(scores(extractor.select_with_coverage(keypoints(), 3)), vec![0.9, 0.5, 0.4]);
+        // Leftover budget goes to the strongest remaining keypoint
+        assert_eq!(scores(extractor.select_with_coverage(keypoints(), 5)), vec![0.9, 0.85, 0.5, 0.4, 0.3]);
+    }
+
+    #[test]
+    fn border_drops_edge_detections_only() {
+        let mut values = vec![0.0f32; 32 * 32];
+        for (x, y) in [(2, 16), (16, 1), (29, 16), (16, 30), (16, 16), (4, 27)] {
+            values[y * 32 + x] = 0.9;
+        }
+        let heatmap = Tensor::from_slice(&values).view((32, 32));
+        let positions = |keypoints: Vec<Keypoint>| {
+            let mut positions: Vec<(f32, f32)> = keypoints.iter().map(|kp| (kp.x, kp.y)).collect();
+            positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            positions
+        };
+        
+        assert_eq!(extractor(|_| {}).extract_keypoints(&heatmap).unwrap().len(), 6);
+        
+        let kept = extractor(|config| config.border = Some(4)).extract_keypoints(&heatmap).unwrap();
+        assert_eq!(positions(kept), vec![(4.0, 27.0), (16.0, 16.0)]);
+    }
+
+    #[test]
+    fn block_thresholding_matches_whole_heatmap() {
+        let heatmap = Tensor::rand([61, 48], (Kind::Float, Device::Cpu));
+        let positions = |keypoints: Vec<Keypoint>| keypoints.iter().map(|kp| (kp.x, kp.y, kp.score)).collect::<Vec<_>>();
+        
+        let whole = extractor(|_| {}).extract_keypoints(&heatmap).unwrap();
+        for block_rows in [1, 7, 16, 100] {
+            let blocked = extractor(|config| config.block_rows = Some(block_rows))
+                .extract_keypoints(&heatmap)
+                .unwrap();
+            assert_eq!(positions(blocked), positions(whole.clone()), "block_rows = {}", block_rows);
+        }
+    }
+
+    /// The original greedy O(n²) radius NMS, kept as the reference for the spatial-hash version.
+    fn quadratic_nms(mut keypoints: Vec<Keypoint>, radius: f32) -> Vec<Keypoint> {
+        keypoints.sort_by(Keypoint::cmp_by_score);
+        let mut suppressed = vec![false; keypoints.len()];
+        let mut result = Vec::new();
+        
+        for i in 0..keypoints.len() {
+            if suppressed[i] {
+                continue;
+            }
+            result.push(keypoints[i].clone());
+            for j in (i + 1)..keypoints.len() {
+                if keypoints[i].distance_to(&keypoints[j]) < radius {
+                    suppressed[j] = true;
+                }
+            }
+        }
+        
+        result
+    }
+    
+    /// `count` keypoints with reproducible pseudo-random positions in `size` x `size` and scores.
+    fn scattered_keypoints(count: usize, size: f32) -> Vec<Keypoint> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (state >> 40) as f32 / (1u64 << 24) as f32
+        };
+        (0..count)
+            .map(|_| Keypoint::new(next() * size, next() * size, next()))
+            .collect()
+    }
+    
+    #[test]
+    fn spatial_nms_matches_quadratic_reference() {
+        let extractor = extractor(|_| {});
+        let keypoints = scattered_keypoints(2_000, 200.0);
+        
+        for radius in [0.5, 4.0, 9.5] {
+            assert_eq!(
+                extractor.apply_nms(keypoints.clone(), radius),
+                quadratic_nms(keypoints.clone(), radius),
+                "radius = {}",
+                radius
+            );
+        }
+    }
+    
+    #[test]
+    #[ignore = "benchmark; run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_spatial_nms_against_quadratic() {
+        let extractor = extractor(|_| {});
+        let keypoints = scattered_keypoints(10_000, 640.0);
+        
+        let start = std::time::Instant::now();
+        let spatial = extractor.apply_nms(keypoints.clone(), 4.0);
+        let spatial_time = start.elapsed();
+        
+        let start = std::time::Instant::now();
+        let quadratic = quadratic_nms(keypoints, 4.0);
+        let quadratic_time = start.elapsed();
+        
+        println!(
+            "10k keypoints: spatial hash {:?}, O(n²) {:?} ({} kept)",
+            spatial_time,
+            quadratic_time,
+            spatial.len()
+        );
+        assert_eq!(spatial, quadratic);
+        assert!(spatial_time < quadratic_time);
+    }
+
+    #[test]
+    fn model_center_maps_to_original_center() {
+        let extractor = extractor(|_| {});
+        let original = OriginalDims::new(640, 480);
+        let model = ModelDims::new(320, 240);
+        
+        let scaled = extractor.scale_keypoints_to_original(vec![Keypoint::new(160.0, 120.0, 1.0)], original, model, None);
+        
+        assert_eq!((scaled[0].x, scaled[0].y), (320.0, 240.0));
+    }
+
+    #[test]
+    fn exclusion_rectangle_drops_only_keypoints_inside() {
+        let extractor = extractor(|config| config.exclude_regions = vec![[10, 20, 30, 5]]);
+        let keypoints = vec![
+            Keypoint::new(10.0, 20.0, 0.9),
+            Keypoint::new(39.5, 24.5, 0.8),
+            Keypoint::new(40.0, 22.0, 0.7),
+            Keypoint::new(20.0, 25.0, 0.6),
+        ];
+        
+        let kept = extractor.apply_exclusions(keypoints);
+        
+        let positions: Vec<_> = kept.iter().map(|kp| (kp.x, kp.y)).collect();
+        assert_eq!(positions, vec![(40.0, 22.0), (20.0, 25.0)]);
+    }
+
+    #[test]
+    fn nms_output_is_independent_of_input_order_for_tied_scores() {
+        let extractor = extractor(|_| {});
+        let keypoints: Vec<Keypoint> = (0..6)
+            .flat_map(|y| (0..6).map(move |x| Keypoint::new(x as f32 * 3.0, y as f32 * 3.0, 0.5)))
+            .collect();
+        let mut reversed = keypoints.clone();
+        reversed.reverse();
+        
+        let forward = extractor.apply_nms(keypoints, 4.0);
+        
+        assert_eq!(forward, extractor.apply_nms(reversed, 4.0));
+        assert_eq!((forward[0].x, forward[0].y), (0.0, 0.0));
+    }
+
+    #[test]
+    fn nan_heatmap_values_are_dropped_without_panicking() {
+        let extractor = extractor(|config| {
+            config.threshold = 0.1;
+            config.border = None;
+        });
+        let mut values = vec![0.0f32; 32 * 32];
+        values[5 * 32 + 5] = 0.9;
+        values[20 * 32 + 20] = 0.7;
+        values[10 * 32 + 25] = f32::NAN;
+        values[25 * 32 + 8] = f32::NAN;
+        let heatmap = Tensor::from_slice(&values).view((32, 32));
+        
+        let keypoints = extractor.extract_keypoints(&heatmap).unwrap();
+        
+        let positions: Vec<_> = keypoints.iter().map(|kp| (kp.x, kp.y)).collect();
+        assert_eq!(positions, vec![(5.0, 5.0), (20.0, 20.0)]);
+    }
+
+    /// The original extraction loop: one `get(row).get(col)` tensor per candidate.
+    fn per_pixel_candidates(heatmap: &Tensor, threshold: f64) -> Vec<Keypoint> {
+        let coords: Vec<i64> = Vec::try_from(heatmap.gt(threshold).nonzero().view((-1,))).unwrap();
+        coords
+            .chunks_exact(2)
+            .map(|chunk| {
+                let (row, col) = (chunk[0], chunk[1]);
+                let score = heatmap.get(row).get(col).double_value(&[]) as f32;
+                Keypoint::new(col as f32, row as f32, score)
+            })
+            .collect()
+    }
+    
+    /// A uniform-noise heatmap where about `expected` pixels exceed the returned threshold.
+    fn noise_heatmap(height: i64, width: i64, expected: usize) -> (Tensor, f64) {
+        tch::manual_seed(0);
+        let heatmap = Tensor::rand([height, width], (Kind::Float, Device::Cpu));
+        (heatmap, 1.0 - expected as f64 / (height * width) as f64)
+    }
+    
+    #[test]
+    fn gathered_candidates_match_per_pixel_reads() {
+        let extractor = extractor(|config| config.suppress_grid_artifacts = false);
+        let (heatmap, threshold) = noise_heatmap(48, 64, 300);
+        
+        let gathered = extractor.extract_candidates(&heatmap, threshold).unwrap();
+        
+        assert!(!gathered.is_empty());
+        assert_eq!(gathered, per_pixel_candidates(&heatmap, threshold));
+    }
+    
+    #[test]
+    #[ignore = "benchmark; run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_gathered_candidates_against_per_pixel_reads() {
+        let extractor = extractor(|config| config.suppress_grid_artifacts = false);
+        let (heatmap, threshold) = noise_heatmap(480, 640, 5_000);
+        
+        let start = std::time::Instant::now();
+        let gathered = extractor.extract_candidates(&heatmap, threshold).unwrap();
+        let gathered_time = start.elapsed();
+        
+        let start = std::time::Instant::now();
+        let per_pixel = per_pixel_candidates(&heatmap, threshold);
+        let per_pixel_time = start.elapsed();
+        
+        println!(
+            "{} candidates: index_select {:?}, per-pixel get {:?}",
+            gathered.len(),
+            gathered_time,
+            per_pixel_time
+        );
+        assert_eq!(gathered, per_pixel);
+        assert!(gathered_time < per_pixel_time);
+    }
+
+    #[test]
+    fn grid_aligned_marginal_candidates_are_suppressed() {
+        let extractor = extractor(|config| config.suppress_grid_artifacts = true);
+        let mut values = vec![0.0f32; 32 * 32];
+        // Marginal grid-aligned artifacts, marginal off-grid detections and one strong grid-aligned peak
+        values[8 * 32 + 8] = 0.15;
+        values[24 * 32 + 16] = 0.12;
+        values[13 * 32 + 5] = 0.15;
+        values[3 * 32 + 20] = 0.12;
+        values[16 * 32 + 24] = 0.9;
+        let heatmap = Tensor::from_slice(&values).view((32, 32));
+        
+        let candidates = extractor.extract_candidates(&heatmap, 0.1).unwrap();
+        
+        let positions: Vec<_> = candidates.iter().map(|kp| (kp.x, kp.y)).collect();
+        assert_eq!(positions, vec![(20.0, 3.0), (5.0, 13.0), (24.0, 16.0)]);
+    }
+}
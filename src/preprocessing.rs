@@ -1,7 +1,46 @@
-use crate::config::ImageConfig;
+use crate::config::{AspectMode, GrayscaleMode, ImageConfig, Interpolation, NormalizationMode};
 use crate::error::SuperPointError;
-use image::{DynamicImage, GrayImage, ImageBuffer};
-use tch::{Device, Tensor};
+use crate::geometry::{ModelDims, OriginalDims};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageBuffer, Luma};
+use std::path::Path;
+use tch::{Device, Kind, Tensor};
+
+/// Channel-averaged ImageNet mean and standard deviation, for single-channel input.
+const IMAGENET_GRAY_MEAN: f32 = (0.485 + 0.456 + 0.406) / 3.0;
+const IMAGENET_GRAY_STD: f32 = (0.229 + 0.224 + 0.225) / 3.0;
+
+/// Placement of a letterboxed image inside the model input: resized by `scale` (the same on
+/// both axes), then shifted by `(offset_x, offset_y)` model pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Letterbox {
+    pub scale: f32,
+    pub offset_x: u32,
+    pub offset_y: u32,
+}
+
+impl Letterbox {
+    /// Centers an `original_size` image inside a `model_size` input at the largest scale that fits.
+    pub fn fit(original_size: OriginalDims, model_size: ModelDims) -> Self {
+        let scale = (model_size.width as f32 / original_size.width as f32)
+            .min(model_size.height as f32 / original_size.height as f32);
+        let (resized_width, resized_height) = scaled_size((original_size.width, original_size.height), scale);
+        
+        Self {
+            scale,
+            offset_x: (model_size.width as u32).saturating_sub(resized_width) / 2,
+            offset_y: (model_size.height as u32).saturating_sub(resized_height) / 2,
+        }
+    }
+    
+    /// The letterbox `config` applies to an `original_size` image, or `None` in `Stretch` mode.
+    pub fn for_config(config: &ImageConfig, original_size: OriginalDims) -> Option<Self> {
+        match config.aspect_mode {
+            AspectMode::Stretch => None,
+            AspectMode::Letterbox => Some(Self::fit(original_size, ModelDims::from_config(config))),
+        }
+    }
+}
 
 pub struct ImagePreprocessor {
     config: ImageConfig,
@@ -19,22 +58,121 @@ impl ImagePreprocessor {
             .map_err(|e| SuperPointError::ImageProcessing(format!("Failed to load image '{}': {}", image_path, e)))?;
         
         // Create tensor for model input
-        let tensor = self.create_tensor_from_image(&original_image)?;
+        let tensor = self.preprocess_image(&original_image)?;
+        
+        Ok((tensor, original_image))
+    }
+    
+    /// Preprocesses an already-decoded image into a `[1, 1, H, W]` model input, skipping disk IO.
+    /// The image is only borrowed, so callers can keep it for drawing or rescaling keypoints;
+    /// inputs for a batch can be concatenated along dim 0.
+    pub fn preprocess_image(&self, image: &DynamicImage) -> Result<Tensor, SuperPointError> {
+        self.create_tensor_from_image(image)
+    }
+    
+    /// Decodes an in-memory encoded image (JPEG, PNG, ...) and preprocesses it like
+    /// `load_and_preprocess`, without touching the filesystem.
+    pub fn preprocess_bytes(&self, bytes: &[u8]) -> Result<(Tensor, DynamicImage), SuperPointError> {
+        let original_image = image::load_from_memory(bytes)
+            .map_err(|e| SuperPointError::ImageProcessing(format!("Failed to decode image bytes: {}", e)))?;
+        
+        let tensor = self.preprocess_image(&original_image)?;
         
         Ok((tensor, original_image))
     }
     
+    /// Splits an image into `tile_width` x `tile_height` crops overlapping by `overlap` pixels,
+    /// returned with their `(x, y)` origin. The last row/column of tiles is shifted inwards so
+    /// every tile lies inside the image; images smaller than a tile yield a single crop.
+    pub fn split_into_tiles(
+        &self,
+        image: &DynamicImage,
+        tile_width: u32,
+        tile_height: u32,
+        overlap: u32,
+    ) -> Vec<((u32, u32), DynamicImage)> {
+        let (width, height) = image.dimensions();
+        let xs = tile_origins(width, tile_width, overlap);
+        let ys = tile_origins(height, tile_height, overlap);
+        
+        ys.iter()
+            .flat_map(|&y| xs.iter().map(move |&x| (x, y)))
+            .map(|(x, y)| {
+                let tile = image.crop_imm(x, y, tile_width.min(width), tile_height.min(height));
+                ((x, y), tile)
+            })
+            .collect()
+    }
+    
+    /// Letterbox placement for an `original_size` image, or `None` when inputs are stretched.
+    pub fn letterbox(&self, original_size: OriginalDims) -> Option<Letterbox> {
+        Letterbox::for_config(&self.config, original_size)
+    }
+    
+    /// Loads a region-of-interest mask drawn over the input image and maps it to model space
+    /// the same way images are (stretched or letterboxed, with the padding masked out).
+    pub fn load_mask(&self, path: &Path) -> Result<GrayImage, SuperPointError> {
+        let mask = image::open(path)
+            .map_err(|e| SuperPointError::ImageProcessing(format!("Failed to load mask '{}': {}", path.display(), e)))?
+            .to_luma8();
+        let (model_width, model_height) = (self.config.width as u32, self.config.height as u32);
+        
+        // Nearest neighbour keeps the mask binary
+        Ok(match self.letterbox(mask.dimensions().into()) {
+            None => image::imageops::resize(&mask, model_width, model_height, image::imageops::FilterType::Nearest),
+            Some(letterbox) => {
+                let (width, height) = scaled_size(mask.dimensions(), letterbox.scale);
+                let fitted = image::imageops::resize(&mask, width, height, image::imageops::FilterType::Nearest);
+                
+                let mut canvas = GrayImage::new(model_width, model_height);
+                image::imageops::replace(&mut canvas, &fitted, letterbox.offset_x as i64, letterbox.offset_y as i64);
+                canvas
+            }
+        })
+    }
+    
     pub fn create_tensor_from_image(&self, image: &DynamicImage) -> Result<Tensor, SuperPointError> {
         // Convert to grayscale
-        let gray_image = image.to_luma8();
+        let gray_image = self.to_grayscale(image);
+        
+        self.create_tensor_from_gray(&gray_image)
+    }
+    
+    /// Builds the model input straight from a row-major 8-bit grayscale buffer.
+    pub fn create_tensor_from_gray_buffer(&self, buf: &[u8], width: u32, height: u32) -> Result<Tensor, SuperPointError> {
+        if buf.len() != width as usize * height as usize {
+            return Err(SuperPointError::ImageProcessing(format!(
+                "Grayscale buffer has {} bytes, expected {}x{} = {}",
+                buf.len(),
+                width,
+                height,
+                width as usize * height as usize
+            )));
+        }
+        
+        let gray_image = GrayImage::from_raw(width, height, buf.to_vec())
+            .ok_or_else(|| SuperPointError::ImageProcessing("Failed to create image buffer".to_string()))?;
+        
+        self.create_tensor_from_gray(&gray_image)
+    }
+    
+    fn create_tensor_from_gray(&self, gray_image: &GrayImage) -> Result<Tensor, SuperPointError> {
+        let (model_width, model_height) = (self.config.width as u32, self.config.height as u32);
+        
+        let filter = filter_type(self.config.interpolation);
         
         // Resize to model input dimensions
-        let resized = image::imageops::resize(
-            &gray_image,
-            self.config.width as u32,
-            self.config.height as u32,
-            image::imageops::FilterType::Lanczos3,
-        );
+        let resized = match self.letterbox(gray_image.dimensions().into()) {
+            None => image::imageops::resize(gray_image, model_width, model_height, filter),
+            Some(letterbox) => {
+                let (width, height) = scaled_size(gray_image.dimensions(), letterbox.scale);
+                let fitted = image::imageops::resize(gray_image, width, height, filter);
+                
+                let mut canvas = GrayImage::from_pixel(model_width, model_height, Luma([self.config.letterbox_fill]));
+                image::imageops::replace(&mut canvas, &fitted, letterbox.offset_x as i64, letterbox.offset_y as i64);
+                canvas
+            }
+        };
         
         // Convert to tensor
         let tensor = self.image_to_tensor(&resized)?;
@@ -42,26 +180,55 @@ impl ImagePreprocessor {
         Ok(tensor)
     }
     
+    fn to_grayscale(&self, image: &DynamicImage) -> GrayImage {
+        match self.config.grayscale_mode {
+            GrayscaleMode::Luma => image.to_luma8(),
+            GrayscaleMode::OpenCvCompat => {
+                // OpenCV uses 0.299/0.587/0.114 in 14-bit fixed point with round-half-up
+                const SHIFT: u32 = 14;
+                const R_WEIGHT: u32 = 4899;
+                const G_WEIGHT: u32 = 9617;
+                const B_WEIGHT: u32 = 1868;
+                
+                let rgb = image.to_rgb8();
+                GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+                    let [r, g, b] = rgb.get_pixel(x, y).0;
+                    let luma = (r as u32 * R_WEIGHT + g as u32 * G_WEIGHT + b as u32 * B_WEIGHT
+                        + (1 << (SHIFT - 1)))
+                        >> SHIFT;
+                    image::Luma([luma as u8])
+                })
+            }
+        }
+    }
+    
     fn image_to_tensor(&self, image: &GrayImage) -> Result<Tensor, SuperPointError> {
         let (width, height) = image.dimensions();
+        let normalization = self.config.normalization_mode();
         let pixels: Vec<f32> = image
             .pixels()
             .map(|pixel| {
-                let value = pixel[0] as f32 / 255.0;
-                if self.config.normalize {
-                    // Normalize to [-1, 1] or [0, 1] depending on model requirements
-                    value
-                } else {
-                    pixel[0] as f32
+                let value = pixel[0] as f32;
+                match normalization {
+                    NormalizationMode::ZeroToOne => value / 255.0,
+                    NormalizationMode::MinusOneToOne => value / 127.5 - 1.0,
+                    NormalizationMode::Raw => value,
+                    NormalizationMode::ImageNet => (value / 255.0 - IMAGENET_GRAY_MEAN) / IMAGENET_GRAY_STD,
                 }
             })
             .collect();
 
-        let tensor = Tensor::from_slice(&pixels)
-            .view((1, 1, height as i64, width as i64))
-            .to_device(self.device);
+        let tensor = Tensor::from_slice(&pixels).view((1, 1, height as i64, width as i64));
 
-        Ok(tensor)
+        if self.config.pin_memory && self.device.is_cuda() {
+            // Page-locked host memory allows a non-blocking host-to-device copy
+            let pinned = tensor
+                .f_pin_memory(self.device)
+                .map_err(|e| SuperPointError::ImageProcessing(format!("Failed to pin input tensor: {}", e)))?;
+            return Ok(pinned.to_device_(self.device, Kind::Float, true, false));
+        }
+
+        Ok(tensor.to_device(self.device))
     }
     
     pub fn tensor_to_image(&self, tensor: &Tensor) -> Result<GrayImage, SuperPointError> {
@@ -88,4 +255,97 @@ impl ImagePreprocessor {
         ImageBuffer::from_raw(width, height, pixels)
             .ok_or_else(|| SuperPointError::ImageProcessing("Failed to create image buffer".to_string()))
     }
-} 
\ No newline at end of file
+} 
+
+/// `size` scaled by `scale`, rounded and at least one pixel on each axis.
+fn scaled_size((width, height): (u32, u32), scale: f32) -> (u32, u32) {
+    (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    )
+}
+
+fn filter_type(interpolation: Interpolation) -> FilterType {
+    match interpolation {
+        Interpolation::Nearest => FilterType::Nearest,
+        Interpolation::Triangle => FilterType::Triangle,
+        Interpolation::CatmullRom => FilterType::CatmullRom,
+        Interpolation::Gaussian => FilterType::Gaussian,
+        Interpolation::Lanczos3 => FilterType::Lanczos3,
+    }
+}
+
+/// Start offsets of tiles covering `length` pixels, the last one flush with the end.
+fn tile_origins(length: u32, tile: u32, overlap: u32) -> Vec<u32> {
+    if length <= tile {
+        return vec![0];
+    }
+    
+    let stride = tile.saturating_sub(overlap).max(1);
+    let mut origins: Vec<u32> = (0..)
+        .map(|i| i * stride)
+        .take_while(|&origin| origin + tile < length)
+        .collect();
+    origins.push(length - tile);
+    origins
+}
+
+/// Lower-case file extensions this build can decode, given the enabled `image` features.
+pub fn supported_formats() -> Vec<&'static str> {
+    image::ImageFormat::all()
+        .filter(|format| format.reading_enabled())
+        .flat_map(|format| format.extensions_str().iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn opencv_compat_grayscale_matches_opencv() {
+        let mut config = Config::default().image;
+        config.grayscale_mode = GrayscaleMode::OpenCvCompat;
+        let preprocessor = ImagePreprocessor::new(config, Device::Cpu);
+        
+        // Expected values from cv2.cvtColor(..., cv2.COLOR_RGB2GRAY)
+        let cases: [([u8; 3], u8); 5] = [
+            ([255, 0, 0], 76),
+            ([0, 255, 0], 150),
+            ([0, 0, 255], 29),
+            ([100, 150, 200], 141),
+            ([255, 255, 255], 255),
+        ];
+        
+        let rgb = image::RgbImage::from_fn(cases.len() as u32, 1, |x, _| image::Rgb(cases[x as usize].0));
+        let gray = preprocessor.to_grayscale(&DynamicImage::ImageRgb8(rgb));
+        
+        for (x, (_, expected)) in cases.iter().enumerate() {
+            let actual = gray.get_pixel(x as u32, 0).0[0];
+            assert!(actual.abs_diff(*expected) <= 1, "pixel {}: got {}, expected {}", x, actual, expected);
+        }
+    }
+
+    #[test]
+    fn pin_memory_is_ignored_on_cpu() {
+        let mut config = Config::default().image;
+        config.pin_memory = true;
+        let preprocessor = ImagePreprocessor::new(config, Device::Cpu);
+        
+        let tensor = preprocessor.preprocess_image(&DynamicImage::new_luma8(64, 48)).unwrap();
+        
+        assert_eq!(tensor.device(), Device::Cpu);
+        assert_eq!(tensor.size(), vec![1, 1, 240, 320]);
+    }
+
+    #[test]
+    fn supported_formats_cover_common_extensions() {
+        let formats = supported_formats();
+        
+        for extension in ["png", "jpg", "jpeg"] {
+            assert!(formats.contains(&extension), "{} missing from {:?}", extension, formats);
+        }
+        assert!(!formats.contains(&"txt"));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::config::Config;
+use crate::detector::{DetectionOutput, Timings};
+use crate::error::SuperPointError;
+use crate::keypoint::Keypoint;
+use image::{DynamicImage, GenericImageView};
+use serde::Serialize;
+use std::path::Path;
+use tch::Device;
+
+/// One run's provenance and results, serialized as JSON for experiment tracking.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub config: Config,
+    /// FNV-1a 64-bit hash of the decoded pixel bytes, as 16 hex digits
+    pub input_hash: String,
+    pub input_width: u32,
+    pub input_height: u32,
+    pub device: String,
+    pub preprocessing_ms: f64,
+    pub inference_ms: f64,
+    pub extraction_ms: f64,
+    pub scaling_ms: f64,
+    pub keypoint_count: usize,
+    pub score_stats: Option<ScoreStats>,
+}
+
+/// Summary of keypoint scores; absent when nothing was detected.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ScoreStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+
+impl Report {
+    pub fn new(config: &Config, image: &DynamicImage, output: &DetectionOutput) -> Self {
+        Self::from_keypoints(config, image, &output.keypoints, output.device, &output.timings)
+    }
+
+    /// Builds a report from any detection result, e.g. the keypoints and timings returned by
+    /// `Detector::detect_timed` for tiled or pyramid runs.
+    pub fn from_keypoints(
+        config: &Config,
+        image: &DynamicImage,
+        keypoints: &[Keypoint],
+        device: Device,
+        timings: &Timings,
+    ) -> Self {
+        let (input_width, input_height) = image.dimensions();
+        let scores: Vec<f32> = keypoints.iter().map(|kp| kp.score).collect();
+
+        let score_stats = (!scores.is_empty()).then(|| ScoreStats {
+            min: scores.iter().copied().fold(f32::INFINITY, f32::min),
+            max: scores.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+            mean: scores.iter().sum::<f32>() / scores.len() as f32,
+        });
+
+        Self {
+            config: config.clone(),
+            input_hash: format!("{:016x}", fnv1a(image.as_bytes())),
+            input_width,
+            input_height,
+            device: format!("{:?}", device),
+            preprocessing_ms: timings.preprocessing.as_secs_f64() * 1000.0,
+            inference_ms: timings.inference.as_secs_f64() * 1000.0,
+            extraction_ms: timings.extraction.as_secs_f64() * 1000.0,
+            scaling_ms: timings.scaling.as_secs_f64() * 1000.0,
+            keypoint_count: keypoints.len(),
+            score_stats,
+        }
+    }
+
+    pub fn to_file(&self, path: impl AsRef<Path>) -> Result<(), SuperPointError> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| SuperPointError::Io(std::io::Error::other(e)))?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,58 @@
+use crate::config::Config;
+use crate::detector::Detector;
+use crate::error::SuperPointError;
+use crate::keypoint::Keypoint;
+use image::{DynamicImage, RgbImage};
+use std::path::Path;
+use video_rs::decode::Decoder;
+
+/// Runs detection on every frame of a video file.
+///
+/// The model is loaded once and reused for all frames. When `output_dir` is non-empty, each
+/// annotated frame is written there as `frame_000000.png`, `frame_000001.png`, ...; pass `""`
+/// to only collect keypoints. Returns one keypoint list per decoded frame, in frame order;
+/// decode errors other than reaching the end of the stream abort with an error.
+pub fn process_video(config: &Config, input: &str, output_dir: &str) -> Result<Vec<Vec<Keypoint>>, SuperPointError> {
+    let video_error =
+        |e: video_rs::Error| SuperPointError::ImageProcessing(format!("Failed to decode video '{}': {}", input, e));
+
+    video_rs::init().map_err(video_error)?;
+    let mut decoder = Decoder::new(Path::new(input)).map_err(video_error)?;
+    let (width, height) = decoder.size();
+
+    let detector = Detector::new(config)?;
+    if !output_dir.is_empty() {
+        std::fs::create_dir_all(output_dir)?;
+    }
+
+    let mut frames_keypoints = Vec::new();
+    for decoded in decoder.decode_iter() {
+        // The iterator never ends on its own; end-of-stream arrives as an exhausted error
+        let (_, frame) = match decoded {
+            Ok(decoded) => decoded,
+            Err(video_rs::Error::ReadExhausted | video_rs::Error::DecodeExhausted) => break,
+            Err(e) => return Err(video_error(e)),
+        };
+
+        let pixels = frame
+            .as_slice()
+            .ok_or_else(|| SuperPointError::ImageProcessing("Decoded frame is not contiguous".to_string()))?
+            .to_vec();
+        let image = RgbImage::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageRgb8)
+            .ok_or_else(|| SuperPointError::ImageProcessing("Failed to create image buffer".to_string()))?;
+
+        let keypoints = if output_dir.is_empty() {
+            detector.detect_image(&image)?
+        } else {
+            let (keypoints, annotated) = detector.detect_and_annotate(&image)?;
+            let frame_path = Path::new(output_dir).join(format!("frame_{:06}.png", frames_keypoints.len()));
+            annotated.save(frame_path)?;
+            keypoints
+        };
+
+        frames_keypoints.push(keypoints);
+    }
+
+    Ok(frames_keypoints)
+}
@@ -1,8 +1,45 @@
-use crate::config::VisualizationConfig;
+use crate::config::{Colormap, VisualizationConfig};
 use crate::error::SuperPointError;
-use crate::keypoint::Keypoint;
+use crate::keypoint::{AffineRegion, Keypoint};
 use image::{DynamicImage, Rgb, RgbImage};
-use imageproc::drawing::{draw_filled_circle_mut, draw_line_segment_mut};
+use imageproc::drawing::{draw_filled_circle_mut, draw_hollow_circle_mut, draw_line_segment_mut};
+
+const LABEL_PALETTE: [[u8; 3]; 10] = [
+    [230, 25, 75],
+    [60, 180, 75],
+    [255, 225, 25],
+    [0, 130, 200],
+    [245, 130, 48],
+    [145, 30, 180],
+    [70, 240, 240],
+    [240, 50, 230],
+    [210, 245, 60],
+    [250, 190, 190],
+];
+const NOISE_COLOR: [u8; 3] = [128, 128, 128];
+
+/// Width of the colorbar strip appended by `create_heatmap_visualization`: bar, gap and label column.
+pub const COLORBAR_WIDTH: u32 = COLORBAR_BAR_WIDTH + 2 + 6 * 4;
+const COLORBAR_BAR_WIDTH: u32 = 12;
+
+/// 3x5 bitmap glyphs for colorbar labels, one row per byte (bit 2 = leftmost column).
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => return None,
+    })
+}
 
 pub struct Visualizer {
     config: VisualizationConfig,
@@ -14,7 +51,7 @@ impl Visualizer {
     }
 
     pub fn draw_keypoints(&self, image: &DynamicImage, keypoints: &[Keypoint]) -> Result<RgbImage, SuperPointError> {
-        let mut rgb_image = image.to_rgb8();
+        let mut rgb_image = self.background(image);
         let color = Rgb(self.config.circle_color);
         
         for keypoint in keypoints {
@@ -31,7 +68,7 @@ impl Visualizer {
                     color,
                 );
                 
-                // Optionally draw a cross for better visibility
+                // Optionally draw a cross for better visibility (`draw_cross` in config)
                 self.draw_cross(&mut rgb_image, x, y, color);
             }
         }
@@ -40,7 +77,7 @@ impl Visualizer {
     }
     
     pub fn draw_keypoints_with_scores(&self, image: &DynamicImage, keypoints: &[Keypoint]) -> Result<RgbImage, SuperPointError> {
-        let mut rgb_image = image.to_rgb8();
+        let mut rgb_image = self.background(image);
         
         for keypoint in keypoints {
             let x = keypoint.x.round() as i32;
@@ -48,7 +85,7 @@ impl Visualizer {
             
             if x >= 0 && y >= 0 && (x as u32) < rgb_image.width() && (y as u32) < rgb_image.height() {
                 // Color intensity based on score (higher score = brighter red)
-                let intensity = (keypoint.score.clamp(0.0, 1.0) * 255.0) as u8;
+                let intensity = (self.config.score_transform.apply(keypoint.score) * 255.0) as u8;
                 let color = Rgb([intensity, 0, 0]);
                 
                 draw_filled_circle_mut(
@@ -65,6 +102,38 @@ impl Visualizer {
         Ok(rgb_image)
     }
     
+    /// Draws each keypoint as a circle with a radial line pointing along `kp.angle` (radians,
+    /// image coordinates). The radius is `circle_radius` scaled by `kp.scale` when set;
+    /// keypoints without an angle get a plain circle.
+    pub fn draw_oriented_keypoints(&self, image: &DynamicImage, keypoints: &[Keypoint]) -> Result<RgbImage, SuperPointError> {
+        let mut rgb_image = self.background(image);
+        let color = Rgb(self.config.circle_color);
+        
+        for keypoint in keypoints {
+            let x = keypoint.x.round() as i32;
+            let y = keypoint.y.round() as i32;
+            
+            if x >= 0 && y >= 0 && (x as u32) < rgb_image.width() && (y as u32) < rgb_image.height() {
+                let radius = (self.config.circle_radius as f32 * keypoint.scale.unwrap_or(1.0)).max(1.0);
+                
+                match keypoint.angle {
+                    Some(angle) => {
+                        draw_hollow_circle_mut(&mut rgb_image, (x, y), radius.round() as i32, color);
+                        
+                        let (sin, cos) = angle.sin_cos();
+                        let end = (keypoint.x + radius * cos, keypoint.y + radius * sin);
+                        draw_line_segment_mut(&mut rgb_image, (keypoint.x, keypoint.y), end, color);
+                    }
+                    None => draw_filled_circle_mut(&mut rgb_image, (x, y), radius.round() as i32, color),
+                }
+                
+                self.draw_cross(&mut rgb_image, x, y, color);
+            }
+        }
+        
+        Ok(rgb_image)
+    }
+    
     pub fn draw_keypoint_matches(
         &self,
         image1: &DynamicImage,
@@ -95,22 +164,20 @@ impl Visualizer {
             combined.put_pixel(x + w1, y, *pixel);
         }
         
-        // Draw keypoints
+        // Draw keypoints, each only if it lies inside its own half
         let kp_color = Rgb(self.config.circle_color);
-        for kp in keypoints1 {
-            let x = kp.x.round() as i32;
-            let y = kp.y.round() as i32;
-            if x >= 0 && y >= 0 && (x as u32) < w1 && (y as u32) < h1 {
-                draw_filled_circle_mut(&mut combined, (x, y), self.config.circle_radius as i32, kp_color);
-            }
+        let inside = |kp: &Keypoint, width: u32, height: u32| {
+            let (x, y) = (kp.x.round() as i32, kp.y.round() as i32);
+            x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height
+        };
+        
+        for kp in keypoints1.iter().filter(|kp| inside(kp, w1, h1)) {
+            self.draw_marker(&mut combined, kp, kp_color);
         }
         
-        for kp in keypoints2 {
-            let x = (kp.x.round() as u32 + w1) as i32;
-            let y = kp.y.round() as i32;
-            if x >= w1 as i32 && y >= 0 && (x as u32) < combined_width && (y as u32) < h2 {
-                draw_filled_circle_mut(&mut combined, (x, y), self.config.circle_radius as i32, kp_color);
-            }
+        for kp in keypoints2.iter().filter(|kp| inside(kp, w2, h2)) {
+            let shifted = Keypoint::new(kp.x + w1 as f32, kp.y, kp.score);
+            self.draw_marker(&mut combined, &shifted, kp_color);
         }
         
         // Draw match lines
@@ -130,7 +197,162 @@ impl Visualizer {
         Ok(combined)
     }
     
+    /// Draws each keypoint in a deterministic color per cluster label; negative labels (noise) are gray.
+    pub fn draw_keypoints_by_label(
+        &self,
+        image: &DynamicImage,
+        keypoints: &[Keypoint],
+        labels: &[i32],
+    ) -> Result<RgbImage, SuperPointError> {
+        if labels.len() != keypoints.len() {
+            return Err(SuperPointError::ImageProcessing(format!(
+                "Got {} labels for {} keypoints",
+                labels.len(),
+                keypoints.len()
+            )));
+        }
+        
+        let mut rgb_image = self.background(image);
+        
+        for (keypoint, &label) in keypoints.iter().zip(labels) {
+            let color = if label < 0 {
+                Rgb(NOISE_COLOR)
+            } else {
+                Rgb(LABEL_PALETTE[label as usize % LABEL_PALETTE.len()])
+            };
+            
+            self.draw_marker(&mut rgb_image, keypoint, color);
+        }
+        
+        Ok(rgb_image)
+    }
+    
+    /// Outlines each keypoint's suppression footprint as a faint ellipse with the given
+    /// per-axis radii (in image pixels), blended 50/50 with the background.
+    pub fn draw_nms_radius(
+        &self,
+        image: &DynamicImage,
+        keypoints: &[Keypoint],
+        radius_x: f32,
+        radius_y: f32,
+    ) -> Result<RgbImage, SuperPointError> {
+        let mut rgb_image = image.to_rgb8();
+        let [r, g, b] = self.config.circle_color;
+        
+        // One sample per pixel of circumference keeps the outline gap-free
+        let samples = (std::f32::consts::TAU * radius_x.max(radius_y)).ceil().max(8.0) as usize;
+        
+        for keypoint in keypoints {
+            let mut outline: Vec<(u32, u32)> = (0..samples)
+                .filter_map(|i| {
+                    let t = i as f32 / samples as f32 * std::f32::consts::TAU;
+                    let x = (keypoint.x + radius_x * t.cos()).round();
+                    let y = (keypoint.y + radius_y * t.sin()).round();
+                    (x >= 0.0 && y >= 0.0 && (x as u32) < rgb_image.width() && (y as u32) < rgb_image.height())
+                        .then_some((x as u32, y as u32))
+                })
+                .collect();
+            outline.sort_unstable();
+            outline.dedup();
+            
+            for (x, y) in outline {
+                let pixel = rgb_image.get_pixel_mut(x, y);
+                let [pr, pg, pb] = pixel.0;
+                pixel.0 = [
+                    ((pr as u16 + r as u16) / 2) as u8,
+                    ((pg as u16 + g as u16) / 2) as u8,
+                    ((pb as u16 + b as u16) / 2) as u8,
+                ];
+            }
+        }
+        
+        Ok(rgb_image)
+    }
+    
+    /// Draws each affine region as an ellipse outline. Regions must be in the image's coordinates.
+    pub fn draw_affine_regions(&self, image: &DynamicImage, regions: &[AffineRegion]) -> Result<RgbImage, SuperPointError> {
+        const SEGMENTS: usize = 36;
+        
+        let mut rgb_image = self.background(image);
+        let color = Rgb(self.config.circle_color);
+        
+        for region in regions {
+            let (sin, cos) = region.angle.sin_cos();
+            let point = |i: usize| {
+                let t = i as f32 / SEGMENTS as f32 * std::f32::consts::TAU;
+                let (u, v) = (region.major_axis * t.cos(), region.minor_axis * t.sin());
+                (region.x + u * cos - v * sin, region.y + u * sin + v * cos)
+            };
+            
+            for i in 0..SEGMENTS {
+                draw_line_segment_mut(&mut rgb_image, point(i), point(i + 1), color);
+            }
+        }
+        
+        Ok(rgb_image)
+    }
+    
+    pub fn render_montage(
+        &self,
+        images_with_keypoints: &[(DynamicImage, Vec<Keypoint>)],
+        cols: usize,
+    ) -> Result<RgbImage, SuperPointError> {
+        let cols = cols.max(1);
+
+        let annotated: Vec<RgbImage> = images_with_keypoints
+            .iter()
+            .map(|(image, keypoints)| self.draw_keypoints_with_scores(image, keypoints))
+            .collect::<Result<_, _>>()?;
+
+        // Every cell is sized to the largest annotated image; smaller ones are padded
+        let cell_width = annotated.iter().map(|img| img.width()).max().unwrap_or(0);
+        let cell_height = annotated.iter().map(|img| img.height()).max().unwrap_or(0);
+        let rows = annotated.len().div_ceil(cols);
+
+        let mut montage = RgbImage::new(cell_width * cols as u32, cell_height * rows as u32);
+
+        for (i, img) in annotated.iter().enumerate() {
+            let offset_x = (i % cols) as u32 * cell_width;
+            let offset_y = (i / cols) as u32 * cell_height;
+
+            for (x, y, pixel) in img.enumerate_pixels() {
+                montage.put_pixel(x + offset_x, y + offset_y, *pixel);
+            }
+        }
+
+        Ok(montage)
+    }
+
+    /// Base image for keypoint overlays: the original colors untouched, or with
+    /// `grayscale_background` a luma copy dimmed over black by `background_alpha`.
+    fn background(&self, image: &DynamicImage) -> RgbImage {
+        if !self.config.grayscale_background {
+            return image.to_rgb8();
+        }
+        
+        let alpha = self.config.background_alpha.clamp(0.0, 1.0);
+        let gray = image.to_luma8();
+        RgbImage::from_fn(gray.width(), gray.height(), |x, y| {
+            let level = (gray.get_pixel(x, y).0[0] as f32 * alpha).round() as u8;
+            Rgb([level, level, level])
+        })
+    }
+    
+    fn draw_marker(&self, image: &mut RgbImage, keypoint: &Keypoint, color: Rgb<u8>) {
+        let x = keypoint.x.round() as i32;
+        let y = keypoint.y.round() as i32;
+        
+        if x >= 0 && y >= 0 && (x as u32) < image.width() && (y as u32) < image.height() {
+            draw_filled_circle_mut(image, (x, y), self.config.circle_radius as i32, color);
+            self.draw_cross(image, x, y, color);
+        }
+    }
+    
     fn draw_cross(&self, image: &mut RgbImage, x: i32, y: i32, color: Rgb<u8>) {
+        if !self.config.draw_cross {
+            return;
+        }
+        
         let size = (self.config.circle_radius / 2).max(1) as i32;
         
         // Horizontal line
@@ -192,20 +414,399 @@ impl Visualizer {
             heatmap_image.put_pixel(x, y, color);
         }
         
+        if self.config.draw_colorbar {
+            return Ok(self.append_colorbar(&heatmap_image, min_val, max_val));
+        }
+        
         Ok(heatmap_image)
     }
     
+    /// Renders the heatmap like `create_heatmap_visualization` and marks the keypoints on top.
+    /// Keypoints must be in model space, which matches the heatmap resolution.
+    pub fn create_heatmap_with_keypoints(
+        &self,
+        heatmap_tensor: &tch::Tensor,
+        keypoints: &[Keypoint],
+    ) -> Result<RgbImage, SuperPointError> {
+        let mut heatmap_image = self.create_heatmap_visualization(heatmap_tensor)?;
+        let color = Rgb(self.config.circle_color);
+        
+        // The colorbar is appended on the right, so heatmap pixel coordinates are unchanged
+        let heatmap_width = heatmap_tensor.size().last().copied().unwrap_or(0) as f32;
+        for keypoint in keypoints.iter().filter(|kp| kp.x < heatmap_width) {
+            self.draw_marker(&mut heatmap_image, keypoint, color);
+        }
+        
+        Ok(heatmap_image)
+    }
+    
+    /// Adds a vertical colorbar (max at the top, min at the bottom) with value labels on the right edge.
+    fn append_colorbar(&self, heatmap_image: &RgbImage, min_val: f32, max_val: f32) -> RgbImage {
+        let (width, height) = heatmap_image.dimensions();
+        let mut output = RgbImage::new(width + COLORBAR_WIDTH, height);
+        
+        for (x, y, pixel) in heatmap_image.enumerate_pixels() {
+            output.put_pixel(x, y, *pixel);
+        }
+        
+        for y in 0..height {
+            let value = if height > 1 { 1.0 - y as f32 / (height - 1) as f32 } else { 1.0 };
+            let color = self.value_to_heatmap_color(value);
+            for x in width..width + COLORBAR_BAR_WIDTH {
+                output.put_pixel(x, y, color);
+            }
+        }
+        
+        let label_x = width + COLORBAR_BAR_WIDTH + 2;
+        self.draw_label(&mut output, label_x, 0, &format!("{:.3}", max_val));
+        self.draw_label(&mut output, label_x, height.saturating_sub(5), &format!("{:.3}", min_val));
+        
+        output
+    }
+    
+    fn draw_label(&self, image: &mut RgbImage, x: u32, y: u32, text: &str) {
+        let color = Rgb([255, 255, 255]);
+        
+        for (i, rows) in text.chars().filter_map(glyph).enumerate() {
+            for (dy, row) in rows.iter().enumerate() {
+                for dx in 0..3 {
+                    let (px, py) = (x + i as u32 * 4 + dx, y + dy as u32);
+                    if row & (0b100 >> dx) != 0 && px < image.width() && py < image.height() {
+                        image.put_pixel(px, py, color);
+                    }
+                }
+            }
+        }
+    }
+    
+    /// Projects a `[D, Hc, Wc]` (or `[1, D, Hc, Wc]`) descriptor map onto its first three
+    /// principal components and renders them as RGB at the descriptor map's resolution.
+    pub fn render_descriptor_map(&self, descriptors: &tch::Tensor) -> Result<RgbImage, SuperPointError> {
+        use tch::{Device, Kind};
+        
+        let descriptors = match descriptors.dim() {
+            4 if descriptors.size()[0] == 1 => descriptors.squeeze_dim(0),
+            3 => descriptors.shallow_clone(),
+            _ => {
+                return Err(SuperPointError::ImageProcessing(format!(
+                    "Expected descriptor map [D, Hc, Wc] or [1, D, Hc, Wc], got {:?}",
+                    descriptors.size()
+                )));
+            }
+        };
+        
+        let dims = descriptors.size();
+        let (depth, height, width) = (dims[0], dims[1], dims[2]);
+        if depth < 3 {
+            return Err(SuperPointError::ImageProcessing(format!(
+                "Need at least 3 descriptor channels for PCA, got {}",
+                depth
+            )));
+        }
+        
+        // Rows are pixels, columns are descriptor dimensions
+        let samples = descriptors
+            .to_device(Device::Cpu)
+            .to_kind(Kind::Float)
+            .contiguous()
+            .view((depth, height * width))
+            .transpose(0, 1);
+        let centered = &samples - samples.mean_dim(0, true, Kind::Float);
+        
+        // Eigenvectors come back in ascending eigenvalue order, so the top three are last
+        let covariance = centered.transpose(0, 1).matmul(&centered) / ((height * width).max(2) - 1) as f64;
+        let (_, eigenvectors) = covariance.linalg_eigh("L");
+        let components = eigenvectors.narrow(1, depth - 3, 3).flip(1);
+        let projected = centered.matmul(&components);
+        
+        // Normalize each component independently to [0, 1]
+        let min = projected.amin(0, true);
+        let range = (projected.amax(0, true) - &min).clamp_min(1e-12);
+        let normalized = ((projected - min) / range).contiguous().view((-1,));
+        
+        let data: Vec<f32> = Vec::try_from(normalized)
+            .map_err(|e| SuperPointError::ImageProcessing(format!("Failed to convert descriptor PCA: {}", e)))?;
+        let pixels: Vec<u8> = data.iter().map(|&v| (v.clamp(0.0, 1.0) * 255.0) as u8).collect();
+        
+        RgbImage::from_raw(width as u32, height as u32, pixels)
+            .ok_or_else(|| SuperPointError::ImageProcessing("Failed to create descriptor image buffer".to_string()))
+    }
+    
     fn value_to_heatmap_color(&self, value: f32) -> Rgb<u8> {
         let value = value.clamp(0.0, 1.0);
         
-        if value < 0.5 {
-            // Blue to green
-            let t = value * 2.0;
-            Rgb([0, (t * 255.0) as u8, ((1.0 - t) * 255.0) as u8])
-        } else {
-            // Green to red
-            let t = (value - 0.5) * 2.0;
-            Rgb([(t * 255.0) as u8, ((1.0 - t) * 255.0) as u8, 0])
+        match self.config.colormap {
+            Colormap::BlueGreenRed => blue_green_red(value),
+            Colormap::Jet => jet(value),
+            Colormap::Viridis => viridis(value),
+            Colormap::Grayscale => grayscale(value),
+            Colormap::Turbo => turbo(value),
         }
     }
-} 
\ No newline at end of file
+}
+
+/// Blue at 0, green at 0.5, red at 1.
+pub fn blue_green_red(value: f32) -> Rgb<u8> {
+    let value = value.clamp(0.0, 1.0);
+    
+    if value < 0.5 {
+        // Blue to green
+        let t = value * 2.0;
+        Rgb([0, (t * 255.0) as u8, ((1.0 - t) * 255.0) as u8])
+    } else {
+        // Green to red
+        let t = (value - 0.5) * 2.0;
+        Rgb([(t * 255.0) as u8, ((1.0 - t) * 255.0) as u8, 0])
+    }
+}
+
+/// MATLAB's jet: dark blue at 0, dark red at 1, built from three clamped triangle waves.
+pub fn jet(value: f32) -> Rgb<u8> {
+    let value = value.clamp(0.0, 1.0);
+    let channel = |center: f32| to_u8(1.5 - (4.0 * value - center).abs());
+    
+    Rgb([channel(3.0), channel(2.0), channel(1.0)])
+}
+
+/// Matplotlib's viridis, linearly interpolated between nine samples of the reference table.
+pub fn viridis(value: f32) -> Rgb<u8> {
+    const STOPS: [[u8; 3]; 9] = [
+        [68, 1, 84],
+        [71, 45, 123],
+        [59, 82, 139],
+        [44, 114, 142],
+        [33, 145, 140],
+        [40, 174, 128],
+        [94, 201, 98],
+        [173, 220, 48],
+        [253, 231, 37],
+    ];
+    
+    let position = value.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let lower = (position.floor() as usize).min(STOPS.len() - 2);
+    let t = position - lower as f32;
+    let (a, b) = (STOPS[lower], STOPS[lower + 1]);
+    let lerp = |i: usize| (a[i] as f32 + (b[i] as f32 - a[i] as f32) * t).round() as u8;
+    
+    Rgb([lerp(0), lerp(1), lerp(2)])
+}
+
+pub fn grayscale(value: f32) -> Rgb<u8> {
+    let level = to_u8(value);
+    Rgb([level, level, level])
+}
+
+/// Google's turbo colormap via its published degree-5 polynomial approximation.
+pub fn turbo(value: f32) -> Rgb<u8> {
+    let x = value.clamp(0.0, 1.0);
+    let polynomial = |c: [f32; 6]| c[0] + x * (c[1] + x * (c[2] + x * (c[3] + x * (c[4] + x * c[5]))));
+    
+    Rgb([
+        to_u8(polynomial([0.135_721_38, 4.615_392_6, -42.660_323, 132.131_08, -152.942_39, 59.286_38])),
+        to_u8(polynomial([0.091_402_61, 2.194_188_4, 4.842_966_6, -14.185_033, 4.277_298_6, 2.829_566])),
+        to_u8(polynomial([0.106_673_3, 12.641_946, -60.582_05, 110.362_77, -89.903_11, 27.348_25])),
+    ])
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Returns the image's raw bytes in BGR order, for consumers that expect OpenCV-style buffers.
+pub fn to_bgr_bytes(image: &RgbImage) -> Vec<u8> {
+    image
+        .pixels()
+        .flat_map(|pixel| {
+            let [r, g, b] = pixel.0;
+            [b, g, r]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ScoreTransform};
+
+    fn visualizer(configure: impl FnOnce(&mut VisualizationConfig)) -> Visualizer {
+        let mut config = Config::default().visualization;
+        configure(&mut config);
+        Visualizer::new(config)
+    }
+
+    #[test]
+    fn montage_grid_dimensions() {
+        let inputs = vec![
+            (DynamicImage::new_rgb8(40, 30), vec![Keypoint::new(5.0, 5.0, 0.9)]),
+            (DynamicImage::new_rgb8(40, 30), Vec::new()),
+            (DynamicImage::new_rgb8(40, 30), vec![Keypoint::new(10.0, 20.0, 0.5)]),
+        ];
+
+        let montage = visualizer(|_| {}).render_montage(&inputs, 2).unwrap();
+
+        // Three images in two columns need two rows
+        assert_eq!(montage.dimensions(), (80, 60));
+    }
+
+    #[test]
+    fn sqrt_transform_brightens_low_scores() {
+        let image = DynamicImage::new_rgb8(16, 16);
+        let keypoints = [Keypoint::new(8.0, 8.0, 0.25)];
+        let red_at_keypoint = |transform: ScoreTransform| {
+            let rendered = visualizer(|config| config.score_transform = transform)
+                .draw_keypoints_with_scores(&image, &keypoints)
+                .unwrap();
+            rendered.get_pixel(8, 8).0[0]
+        };
+
+        assert!(red_at_keypoint(ScoreTransform::Sqrt) > red_at_keypoint(ScoreTransform::Linear));
+    }
+
+    #[test]
+    fn clusters_get_distinct_colors_and_noise_is_gray() {
+        let image = DynamicImage::new_rgb8(32, 32);
+        let keypoints = [
+            Keypoint::new(5.0, 5.0, 1.0),
+            Keypoint::new(6.0, 25.0, 1.0),
+            Keypoint::new(25.0, 5.0, 1.0),
+            Keypoint::new(25.0, 25.0, 1.0),
+        ];
+
+        let rendered = visualizer(|_| {})
+            .draw_keypoints_by_label(&image, &keypoints, &[0, 0, 1, -1])
+            .unwrap();
+
+        assert_eq!(rendered.get_pixel(5, 5), rendered.get_pixel(6, 25));
+        assert_ne!(rendered.get_pixel(5, 5), rendered.get_pixel(25, 5));
+        assert_eq!(rendered.get_pixel(25, 25), &Rgb(NOISE_COLOR));
+    }
+
+    #[test]
+    fn mismatched_label_count_is_rejected() {
+        let image = DynamicImage::new_rgb8(8, 8);
+
+        let result = visualizer(|_| {}).draw_keypoints_by_label(&image, &[Keypoint::new(1.0, 1.0, 1.0)], &[]);
+
+        assert!(result.is_err());
+    }
+
+    /// Cross arm pixels of a marker at (x, y) for the default radius of 3 (arm length 1).
+    fn cross_arms(x: u32, y: u32) -> [(u32, u32); 4] {
+        [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+    }
+
+    #[test]
+    fn disabled_cross_leaves_oriented_keypoint_centers_blank() {
+        let background = Rgb([40, 40, 40]);
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, background));
+        // A diagonal angle indicator misses every cross arm
+        let keypoints = [Keypoint::with_scale_angle(16.0, 16.0, 1.0, 2.0, std::f32::consts::FRAC_PI_4)];
+
+        let with_cross = visualizer(|_| {}).draw_oriented_keypoints(&image, &keypoints).unwrap();
+        let without_cross = visualizer(|config| config.draw_cross = false)
+            .draw_oriented_keypoints(&image, &keypoints)
+            .unwrap();
+
+        for (x, y) in cross_arms(16, 16) {
+            assert_ne!(with_cross.get_pixel(x, y), &background);
+            assert_eq!(without_cross.get_pixel(x, y), &background);
+        }
+    }
+
+    #[test]
+    fn disabled_cross_draws_only_circles() {
+        let background = Rgb([40, 40, 40]);
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, background));
+        let keypoints = [Keypoint::new(8.0, 8.0, 0.5), Keypoint::new(20.0, 24.0, 1.0)];
+        let visualizer = visualizer(|config| config.draw_cross = false);
+
+        // Scored keypoints: exactly the filled circles, nothing else
+        let scored = visualizer.draw_keypoints_with_scores(&image, &keypoints).unwrap();
+        let mut expected = image.to_rgb8();
+        for kp in &keypoints {
+            let color = Rgb([(kp.score * 255.0) as u8, 0, 0]);
+            draw_filled_circle_mut(&mut expected, (kp.x as i32, kp.y as i32), 3, color);
+        }
+        assert_eq!(scored, expected);
+
+        // Match view: both halves get plain circles through the same marker path
+        let matches = visualizer
+            .draw_keypoint_matches(&image, &image, &keypoints, &keypoints, &[])
+            .unwrap();
+        for kp in &keypoints {
+            for (x, y) in cross_arms(kp.x as u32, kp.y as u32) {
+                assert_eq!(matches.get_pixel(x, y), &Rgb([255, 0, 0]));
+                assert_eq!(matches.get_pixel(x + 32, y), &Rgb([255, 0, 0]));
+            }
+        }
+    }
+
+    #[test]
+    fn bgr_bytes_swap_red_and_blue() {
+        let mut image = RgbImage::new(2, 1);
+        image.put_pixel(0, 0, Rgb([10, 20, 30]));
+        image.put_pixel(1, 0, Rgb([255, 0, 128]));
+
+        assert_eq!(to_bgr_bytes(&image), vec![30, 20, 10, 128, 0, 255]);
+        assert_eq!(image.get_pixel(0, 0), &Rgb([10, 20, 30]));
+    }
+
+    #[test]
+    fn descriptor_map_renders_at_map_resolution() {
+        tch::manual_seed(0);
+        let descriptors = tch::Tensor::randn([1, 16, 5, 7], (tch::Kind::Float, tch::Device::Cpu));
+
+        let rendered = visualizer(|_| {}).render_descriptor_map(&descriptors).unwrap();
+
+        assert_eq!(rendered.dimensions(), (7, 5));
+    }
+
+    #[test]
+    fn nms_radius_outline_is_drawn_at_radius() {
+        let image = DynamicImage::new_rgb8(64, 64);
+        let keypoints = [Keypoint::new(32.0, 32.0, 1.0)];
+
+        let rendered = visualizer(|_| {}).draw_nms_radius(&image, &keypoints, 10.0, 6.0).unwrap();
+
+        // Half of circle_color blended over black, at radius_x horizontally and radius_y vertically
+        let outline = Rgb([127, 0, 0]);
+        for (x, y) in [(42, 32), (22, 32), (32, 38), (32, 26)] {
+            assert_eq!(rendered.get_pixel(x, y), &outline, "({}, {})", x, y);
+        }
+        for (x, y) in [(32, 32), (37, 32), (32, 35), (47, 32)] {
+            assert_eq!(rendered.get_pixel(x, y), &Rgb([0, 0, 0]), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn colorbar_widens_heatmap_and_spans_colormap() {
+        let heatmap = tch::Tensor::arange(48, (tch::Kind::Float, tch::Device::Cpu)).view([6, 8]);
+
+        let plain = visualizer(|_| {}).create_heatmap_visualization(&heatmap).unwrap();
+        let visualizer = visualizer(|config| config.draw_colorbar = true);
+        let with_bar = visualizer.create_heatmap_visualization(&heatmap).unwrap();
+
+        assert_eq!(with_bar.width(), plain.width() + COLORBAR_WIDTH);
+        assert_eq!(with_bar.height(), plain.height());
+        let bar_x = plain.width();
+        assert_eq!(with_bar.get_pixel(bar_x, 0), &visualizer.value_to_heatmap_color(1.0));
+        assert_eq!(with_bar.get_pixel(bar_x, 5), &visualizer.value_to_heatmap_color(0.0));
+    }
+
+    #[test]
+    fn colormap_endpoints() {
+        assert_eq!((grayscale(0.0), grayscale(1.0)), (Rgb([0, 0, 0]), Rgb([255, 255, 255])));
+        assert_eq!((blue_green_red(0.0), blue_green_red(1.0)), (Rgb([0, 0, 255]), Rgb([255, 0, 0])));
+        assert_eq!((jet(0.0), jet(1.0)), (Rgb([0, 0, 128]), Rgb([128, 0, 0])));
+        assert_eq!((viridis(0.0), viridis(1.0)), (Rgb([68, 1, 84]), Rgb([253, 231, 37])));
+
+        // Turbo runs from a dark blue-purple to a dark red
+        let (Rgb([r0, g0, b0]), Rgb([r1, g1, b1])) = (turbo(0.0), turbo(1.0));
+        assert!(b0 > g0 && r0 < 64, "{:?}", turbo(0.0));
+        assert!(r1 > 96 && g1 < 32 && b1 < 16, "{:?}", turbo(1.0));
+
+        // Out-of-range values clamp to the endpoints
+        assert_eq!(viridis(-1.0), viridis(0.0));
+        assert_eq!(jet(2.0), jet(1.0));
+    }
+}
@@ -0,0 +1,36 @@
+"""Writes metadata_getters.pt, the TorchScript fixture for the model metadata test.
+
+`input_height` and `cell_size` are exposed through `@torch.jit.export` getters, which
+is what `SuperPointModel::metadata` reads. `input_width` is only a plain attribute and
+is expected to stay invisible.
+
+    python tests/fixtures/make_metadata_fixture.py
+    cargo test metadata_reads_exported_getters -- --ignored
+"""
+
+from pathlib import Path
+
+import torch
+
+
+class MetadataGetters(torch.nn.Module):
+    def __init__(self):
+        super().__init__()
+        self.input_width = 320
+
+    def forward(self, x: torch.Tensor) -> torch.Tensor:
+        return x
+
+    @torch.jit.export
+    def input_height(self) -> int:
+        return 240
+
+    @torch.jit.export
+    def cell_size(self) -> int:
+        return 8
+
+
+if __name__ == "__main__":
+    output = Path(__file__).with_name("metadata_getters.pt")
+    torch.jit.script(MetadataGetters()).save(str(output))
+    print(f"Wrote {output}")